@@ -0,0 +1,16 @@
+//! Minimal OIDC provider for trying out SSO locally.
+//!
+//! Every authorization request is approved right away for a single user. Run it with
+//! `cargo run --example mock_oidc` and start the server with
+//! `OIDC_ISSUER_URL=http://127.0.0.1:9090 OIDC_CLIENT_ID=book
+//! OIDC_REDIRECT_URL=http://localhost:3031/v2/oidc/callback`.
+//! The user's email can be changed with `MOCK_OIDC_EMAIL`.
+
+mod provider;
+
+#[tokio::main]
+async fn main() {
+    warp::serve(provider::routes_for(vec![provider::User::default()]))
+        .run(([127, 0, 0, 1], 9090))
+        .await;
+}
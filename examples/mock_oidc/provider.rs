@@ -0,0 +1,177 @@
+//! Routes of the mock provider, also used by the tests of `routes::oidc`.
+//!
+//! The issuer is taken from the `Host` header, so it can be served on any port.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use sha2::{Digest, Sha256};
+use warp::{http::StatusCode, http::Uri, Filter, Rejection, Reply};
+
+pub const SUBJECT: &str = "mock-user";
+/// Followed by the subject of the user it was issued for.
+const ACCESS_TOKEN_PREFIX: &str = "mock-access-token:";
+
+/// A user the provider can log in.
+#[derive(Clone)]
+pub struct User {
+    pub subject: String,
+    pub email: String,
+    pub email_verified: bool,
+}
+
+impl Default for User {
+    /// `SUBJECT` with a verified email, `MOCK_OIDC_EMAIL` or sso@example.com.
+    fn default() -> Self {
+        User {
+            subject: SUBJECT.to_string(),
+            email: std::env::var("MOCK_OIDC_EMAIL")
+                .unwrap_or_else(|_| "sso@example.com".to_string()),
+            email_verified: true,
+        }
+    }
+}
+
+/// Code challenges of issued authorization codes, and the subjects they were issued for.
+type Codes = Arc<Mutex<HashMap<String, (String, String)>>>;
+
+fn issuer(host: &str) -> String {
+    format!("http://{}", host)
+}
+
+/// Returns an unsigned ID token, the server doesn't check signatures of tokens it got straight
+/// from the token endpoint.
+fn id_token(issuer: &str, subject: &str, client_id: &str) -> String {
+    let exp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("clock is after 1970")
+        .as_secs()
+        + 300;
+    let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"none","typ":"JWT"}"#);
+    let claims = URL_SAFE_NO_PAD.encode(
+        serde_json::json!({
+            "iss": issuer,
+            "sub": subject,
+            "aud": client_id,
+            "exp": exp,
+        })
+        .to_string(),
+    );
+    format!("{}.{}.", header, claims)
+}
+
+/// Routes of a provider logging in the user whose subject is the `login_hint` of the
+/// authorization request, or the first of users without one.
+pub fn routes_for(
+    users: Vec<User>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let users = Arc::new(users);
+    let users_filter = warp::any().map(move || users.clone());
+    let codes: Codes = Arc::new(Mutex::new(HashMap::new()));
+    let codes_filter = warp::any().map(move || codes.clone());
+
+    let discovery = warp::get()
+        .and(warp::path!(".well-known" / "openid-configuration"))
+        .and(warp::header::<String>("host"))
+        .map(|host: String| {
+            let issuer = issuer(&host);
+            warp::reply::json(&serde_json::json!({
+                "issuer": issuer,
+                "authorization_endpoint": format!("{}/authorize", issuer),
+                "token_endpoint": format!("{}/token", issuer),
+                "userinfo_endpoint": format!("{}/userinfo", issuer),
+            }))
+        });
+
+    let authorize = warp::get()
+        .and(warp::path("authorize"))
+        .and(warp::query::<HashMap<String, String>>())
+        .and(users_filter.clone())
+        .and(codes_filter.clone())
+        .map(
+            |params: HashMap<String, String>, users: Arc<Vec<User>>, codes: Codes| {
+                let subject = match params.get("login_hint") {
+                    Some(hint) => hint.clone(),
+                    None => users[0].subject.clone(),
+                };
+                let code = format!("code-{}", codes.lock().unwrap().len());
+                codes.lock().unwrap().insert(
+                    code.clone(),
+                    (
+                        params.get("code_challenge").cloned().unwrap_or_default(),
+                        subject,
+                    ),
+                );
+                let redirect = format!(
+                    "{}?code={}&state={}",
+                    params.get("redirect_uri").cloned().unwrap_or_default(),
+                    code,
+                    params.get("state").cloned().unwrap_or_default()
+                );
+                warp::redirect::temporary(redirect.parse::<Uri>().unwrap())
+            },
+        );
+
+    let token = warp::post()
+        .and(warp::path("token"))
+        .and(warp::header::<String>("host"))
+        .and(warp::body::form::<HashMap<String, String>>())
+        .and(codes_filter)
+        .map(
+            |host: String, form: HashMap<String, String>, codes: Codes| {
+                let issued = form
+                    .get("code")
+                    .and_then(|code| codes.lock().unwrap().remove(code));
+                let verifier = form.get("code_verifier").cloned().unwrap_or_default();
+                let client_id = form.get("client_id").cloned().unwrap_or_default();
+                match issued {
+                    Some((challenge, subject))
+                        if URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+                            == challenge =>
+                    {
+                        warp::reply::with_status(
+                            warp::reply::json(&serde_json::json!({
+                                "access_token": format!("{}{}", ACCESS_TOKEN_PREFIX, subject),
+                                "token_type": "Bearer",
+                                "id_token": id_token(&issuer(&host), &subject, &client_id),
+                            })),
+                            StatusCode::OK,
+                        )
+                    }
+                    _ => warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({ "error": "invalid_grant" })),
+                        StatusCode::BAD_REQUEST,
+                    ),
+                }
+            },
+        );
+
+    let userinfo = warp::get()
+        .and(warp::path("userinfo"))
+        .and(warp::header::<String>("authorization"))
+        .and(users_filter)
+        .map(|authorization: String, users: Arc<Vec<User>>| {
+            let user = authorization
+                .strip_prefix("Bearer ")
+                .and_then(|token| token.strip_prefix(ACCESS_TOKEN_PREFIX))
+                .and_then(|subject| users.iter().find(|user| user.subject == subject));
+            let Some(user) = user else {
+                return warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({ "error": "invalid_token" })),
+                    StatusCode::UNAUTHORIZED,
+                );
+            };
+            warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({
+                    "sub": user.subject,
+                    "email": user.email,
+                    "email_verified": user.email_verified,
+                })),
+                StatusCode::OK,
+            )
+        });
+
+    discovery.or(authorize).or(token).or(userinfo)
+}
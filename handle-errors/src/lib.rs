@@ -5,31 +5,66 @@ use warp::{
     Rejection, Reply,
 };
 
-use reqwest::Error as ReqwestError;
 use argon2::Error as ArgonError;
-use tracing::{event, instrument, Level};
+use reqwest::Error as ReqwestError;
 use reqwest_middleware::Error as MiddlewareReqwestError;
+use serde::Serialize;
+use tracing::{event, instrument, Level};
+use utoipa::ToSchema;
+
+tokio::task_local! {
+    /// Id of the request being handled, set by the server around every request.
+    pub static REQUEST_ID: String;
+}
 
 #[derive(Debug)]
 pub enum Error {
     ParseError(std::num::ParseIntError),
     MissingParameters,
     WrongPassword,
+    WeakPassword(String),
+    /// Login is locked out, holds the number of seconds until it can be retried.
+    TooManyLoginAttempts(u64),
+    RateLimited(RateLimit),
     CannotDecryptToken,
+    SessionRevoked,
+    MissingCredentials,
+    InvalidApiKey,
+    OidcError(String),
     Unauthorized,
+    NotFound(String),
+    /// The resource changed since the version in `If-Match`.
+    PreconditionFailed,
+    /// `If-Match` is required to change the resource.
+    PreconditionRequired,
+    /// The body doesn't have one of the media types a route accepts.
+    UnsupportedMediaType(String),
+    /// The body can't be parsed.
+    InvalidBody(String),
     ArgonLibraryError(ArgonError),
     DatabaseQueryError(String),
     ExternalAPIError(ReqwestError),
     MiddlewareReqwesAPIError(MiddlewareReqwestError),
     ClientError(APILayerError),
     ServerError(APILayerError),
+    ContentFilterUnavailable,
+}
+
+/// A rate limit that was hit, reported in the `X-RateLimit-*` headers.
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    /// Requests that can be made in a burst.
+    pub limit: u32,
+    /// Seconds until the next request is allowed.
+    pub retry_after: u64,
+    /// Seconds until the full burst is available again.
+    pub reset: u64,
 }
 
 #[derive(Debug, Clone)]
 pub struct APILayerError {
     pub status: u16,
     pub message: String,
-
 }
 
 impl std::fmt::Display for APILayerError {
@@ -46,15 +81,31 @@ impl std::fmt::Display for Error {
             }
             Error::MissingParameters => write!(f, "Missing parameter"),
             Error::WrongPassword => write!(f, "Wrong password "),
+            Error::WeakPassword(ref reason) => write!(f, "Weak password: {}", reason),
+            Error::TooManyLoginAttempts(secs) => {
+                write!(f, "Too many failed logins, retry in {} seconds", secs)
+            }
+            Error::RateLimited(ref limit) => {
+                write!(f, "Rate limited, retry in {} seconds", limit.retry_after)
+            }
             Error::ArgonLibraryError(_) => {
                 write!(f, "cannot verify password")
             }
             Error::CannotDecryptToken => {
                 write!(f, "cannot decrypt token password")
             }
+            Error::SessionRevoked => write!(f, "Session was revoked or expired"),
+            Error::MissingCredentials => write!(f, "Missing token or API key"),
+            Error::InvalidApiKey => write!(f, "Invalid or expired API key"),
+            Error::OidcError(ref s) => write!(f, "SSO login failed: {}", s),
             Error::Unauthorized => {
                 write!(f, "No permission to change resource")
             }
+            Error::NotFound(ref s) => write!(f, "{} not found", s),
+            Error::PreconditionFailed => write!(f, "Resource was changed since it was read"),
+            Error::PreconditionRequired => write!(f, "Missing If-Match header"),
+            Error::UnsupportedMediaType(ref s) => write!(f, "Body has to be {}", s),
+            Error::InvalidBody(ref s) => write!(f, "Invalid body: {}", s),
             Error::DatabaseQueryError(ref s) => {
                 write!(f, "INTERNAL ERROR: {} check server logs", s.clone())
             }
@@ -62,6 +113,7 @@ impl std::fmt::Display for Error {
             Error::MiddlewareReqwesAPIError(ref err) => write!(f, "External API  error: {}", err),
             Error::ClientError(ref err) => write!(f, "External Client error: {}", err),
             Error::ServerError(ref err) => write!(f, "Server Client error: {}", err),
+            Error::ContentFilterUnavailable => write!(f, "Content filter is unavailable"),
         }
     }
 }
@@ -69,71 +121,166 @@ impl std::fmt::Display for Error {
 impl Reject for Error {}
 impl Reject for APILayerError {}
 
+/// Body of every error reply.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ErrorBody {
+    /// What went wrong.
+    pub error: String,
+    /// Id of the request, to find it in the logs.
+    pub request_id: Option<String>,
+}
+
+fn error_reply(error: String, status: StatusCode) -> warp::reply::Response {
+    let body = ErrorBody {
+        error,
+        request_id: REQUEST_ID.try_with(|id| id.clone()).ok(),
+    };
+    warp::reply::with_status(warp::reply::json(&body), status).into_response()
+}
+
 #[instrument]
 pub async fn return_error(r: Rejection) -> Result<impl Reply, Rejection> {
     if let Some(crate::Error::DatabaseQueryError(s)) = r.find() {
         event!(Level::ERROR, "Database query error {}", s);
-        Ok(warp::reply::with_status(
-            s.to_string(),
-            StatusCode::UNPROCESSABLE_ENTITY,
-        ))
+        Ok(error_reply(s.to_string(), StatusCode::UNPROCESSABLE_ENTITY))
     } else if let Some(crate::Error::ExternalAPIError(e)) = r.find() {
         event!(Level::ERROR, "{}", e);
-        Ok(warp::reply::with_status(
+        Ok(error_reply(
             "Internal Server Error".to_string(),
             StatusCode::INTERNAL_SERVER_ERROR,
         ))
     } else if let Some(crate::Error::MiddlewareReqwesAPIError(e)) = r.find() {
         event!(Level::ERROR, "{}", e);
-        Ok(warp::reply::with_status(
+        Ok(error_reply(
             "Internal Server Error".to_string(),
             StatusCode::INTERNAL_SERVER_ERROR,
         ))
     } else if let Some(crate::Error::ClientError(e)) = r.find() {
         event!(Level::ERROR, "{}", e);
-        Ok(warp::reply::with_status(
+        Ok(error_reply(
             "Internal Server Error".to_string(),
             StatusCode::INTERNAL_SERVER_ERROR,
         ))
+    } else if let Some(crate::Error::TooManyLoginAttempts(secs)) = r.find() {
+        event!(Level::WARN, "Login locked out for {} seconds", secs);
+        Ok(warp::reply::with_header(
+            error_reply(
+                "Too many failed login attempts, try again later".to_string(),
+                StatusCode::TOO_MANY_REQUESTS,
+            ),
+            "Retry-After",
+            secs.to_string(),
+        )
+        .into_response())
+    } else if let Some(crate::Error::RateLimited(limit)) = r.find() {
+        event!(
+            Level::WARN,
+            "Rate limited for {} seconds",
+            limit.retry_after
+        );
+        let mut res = error_reply(
+            "Too many requests, try again later".to_string(),
+            StatusCode::TOO_MANY_REQUESTS,
+        );
+        let headers = res.headers_mut();
+        headers.insert("Retry-After", limit.retry_after.into());
+        headers.insert("X-RateLimit-Limit", limit.limit.into());
+        headers.insert("X-RateLimit-Remaining", 0.into());
+        headers.insert("X-RateLimit-Reset", limit.reset.into());
+        Ok(res)
     } else if let Some(crate::Error::WrongPassword) = r.find() {
         event!(Level::ERROR, "Wrong password");
-        Ok(warp::reply::with_status(
+        Ok(error_reply(
             "Wrong email/password combination".to_string(),
             StatusCode::UNAUTHORIZED,
         ))
     } else if let Some(crate::Error::Unauthorized) = r.find() {
         event!(Level::ERROR, "No matching account id");
-        Ok(warp::reply::with_status(
+        Ok(error_reply(
             "No permission to change underlying resource".to_string(),
             StatusCode::UNAUTHORIZED,
         ))
+    } else if let Some(crate::Error::SessionRevoked) = r.find() {
+        event!(Level::ERROR, "Session revoked");
+        Ok(error_reply(
+            "Session was revoked or expired, log in again".to_string(),
+            StatusCode::UNAUTHORIZED,
+        ))
+    } else if let Some(crate::Error::MissingCredentials) = r.find() {
+        event!(Level::ERROR, "Missing credentials");
+        Ok(error_reply(
+            "Missing token or API key".to_string(),
+            StatusCode::UNAUTHORIZED,
+        ))
+    } else if let Some(crate::Error::InvalidApiKey) = r.find() {
+        event!(Level::ERROR, "Invalid API key");
+        Ok(error_reply(
+            "Invalid or expired API key".to_string(),
+            StatusCode::UNAUTHORIZED,
+        ))
+    } else if let Some(crate::Error::OidcError(s)) = r.find() {
+        event!(Level::ERROR, "SSO login failed: {}", s);
+        Ok(error_reply(
+            "SSO login failed".to_string(),
+            StatusCode::UNAUTHORIZED,
+        ))
+    } else if let Some(crate::Error::NotFound(s)) = r.find() {
+        event!(Level::WARN, "{} not found", s);
+        Ok(error_reply(
+            format!("{} not found", s),
+            StatusCode::NOT_FOUND,
+        ))
+    } else if let Some(crate::Error::PreconditionFailed) = r.find() {
+        event!(Level::WARN, "If-Match doesn't match");
+        Ok(error_reply(
+            "Resource was changed since it was read, get it again".to_string(),
+            StatusCode::PRECONDITION_FAILED,
+        ))
+    } else if let Some(crate::Error::PreconditionRequired) = r.find() {
+        event!(Level::WARN, "Missing If-Match");
+        Ok(error_reply(
+            "If-Match with the ETag the resource was read with is required".to_string(),
+            StatusCode::PRECONDITION_REQUIRED,
+        ))
+    } else if let Some(crate::Error::UnsupportedMediaType(s)) = r.find() {
+        event!(Level::WARN, "Body isn't {}", s);
+        Ok(error_reply(
+            format!("Body has to be {}", s),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        ))
+    } else if let Some(crate::Error::InvalidBody(s)) = r.find() {
+        event!(Level::ERROR, "Cannot deserizalize request body: {}", s);
+        Ok(error_reply(s.to_string(), StatusCode::UNPROCESSABLE_ENTITY))
     } else if let Some(crate::Error::ServerError(e)) = r.find() {
         event!(Level::ERROR, "{}", e);
-        Ok(warp::reply::with_status(
+        Ok(error_reply(
             "Internal Server Error".to_string(),
             StatusCode::INTERNAL_SERVER_ERROR,
         ))
+    } else if let Some(crate::Error::ContentFilterUnavailable) = r.find() {
+        event!(Level::ERROR, "Content filter circuit is open");
+        Ok(error_reply(
+            "Service Unavailable".to_string(),
+            StatusCode::SERVICE_UNAVAILABLE,
+        ))
     } else if let Some(error) = r.find::<CorsForbidden>() {
         event!(Level::ERROR, "CORS forbidden error: {}", error);
-        Ok(warp::reply::with_status(
-            error.to_string(),
-            StatusCode::FORBIDDEN,
-        ))
+        Ok(error_reply(error.to_string(), StatusCode::FORBIDDEN))
     } else if let Some(error) = r.find::<BodyDeserializeError>() {
         event!(Level::ERROR, "Cannot deserizalize request body: {}", error);
-        Ok(warp::reply::with_status(
+        Ok(error_reply(
             error.to_string(),
             StatusCode::UNPROCESSABLE_ENTITY,
         ))
     } else if let Some(error) = r.find::<Error>() {
         event!(Level::ERROR, "{}", error);
-        Ok(warp::reply::with_status(
+        Ok(error_reply(
             error.to_string(),
             StatusCode::UNPROCESSABLE_ENTITY,
         ))
     } else {
         event!(Level::WARN, "Requested route was not found");
-        Ok(warp::reply::with_status(
+        Ok(error_reply(
             "Route not found".to_string(),
             StatusCode::NOT_FOUND,
         ))
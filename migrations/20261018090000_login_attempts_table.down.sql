@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS lockout_events;
+DROP TABLE IF EXISTS login_attempts;
@@ -0,0 +1,15 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS login_attempts (
+    key TEXT PRIMARY KEY,
+    failures integer NOT NULL DEFAULT 0,
+    last_failure TIMESTAMP NOT NULL DEFAULT NOW(),
+    locked_until TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS lockout_events (
+    id serial PRIMARY KEY,
+    key TEXT NOT NULL,
+    failures integer NOT NULL,
+    locked_until TIMESTAMP NOT NULL,
+    created_on TIMESTAMP NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,11 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS sessions (
+    id serial PRIMARY KEY,
+    account_id integer NOT NULL,
+    user_agent TEXT,
+    ip TEXT,
+    created_on TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_seen TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_on TIMESTAMPTZ NOT NULL,
+    revoked_on TIMESTAMPTZ
+);
@@ -0,0 +1,5 @@
+-- Add down migration script here
+ALTER TABLE answers
+DROP COLUMN version;
+ALTER TABLE questions
+DROP COLUMN version;
@@ -0,0 +1,4 @@
+-- Add down migration script here
+ALTER TABLE accounts
+DROP COLUMN is_moderator;
+DROP TABLE IF EXISTS question_revisions;
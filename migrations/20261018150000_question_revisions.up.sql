@@ -0,0 +1,20 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS question_revisions (
+    id serial PRIMARY KEY,
+    question_id integer NOT NULL REFERENCES questions ON DELETE CASCADE,
+    version INTEGER NOT NULL,
+    title VARCHAR (255) NOT NULL,
+    content TEXT NOT NULL,
+    tags TEXT [],
+    account_id integer NOT NULL,
+    summary TEXT,
+    created_on TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (question_id, version)
+);
+
+-- Questions so far only have the revision they're at.
+INSERT INTO question_revisions (question_id, version, title, content, tags, account_id, created_on)
+SELECT id, version, title, content, tags, account_id, created_on FROM questions;
+
+ALTER TABLE accounts
+ADD COLUMN is_moderator BOOLEAN NOT NULL DEFAULT false;
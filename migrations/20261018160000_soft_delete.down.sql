@@ -0,0 +1,11 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS answers_deleted_at;
+DROP INDEX IF EXISTS questions_deleted_at;
+
+ALTER TABLE answers
+DROP COLUMN deleted_at,
+DROP COLUMN deleted_by;
+
+ALTER TABLE questions
+DROP COLUMN deleted_at,
+DROP COLUMN deleted_by;
@@ -0,0 +1,12 @@
+-- Add up migration script here
+ALTER TABLE questions
+ADD COLUMN deleted_at TIMESTAMPTZ,
+ADD COLUMN deleted_by INTEGER;
+
+ALTER TABLE answers
+ADD COLUMN deleted_at TIMESTAMPTZ,
+ADD COLUMN deleted_by INTEGER;
+
+-- For the purge job, most rows aren't deleted.
+CREATE INDEX IF NOT EXISTS questions_deleted_at ON questions (deleted_at) WHERE deleted_at IS NOT NULL;
+CREATE INDEX IF NOT EXISTS answers_deleted_at ON answers (deleted_at) WHERE deleted_at IS NOT NULL;
@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS questions_tags;
+DROP TABLE IF EXISTS tag_synonyms;
+DROP TABLE IF EXISTS tags;
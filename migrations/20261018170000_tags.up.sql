@@ -0,0 +1,34 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS tags (
+    id serial PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    description TEXT,
+    wiki TEXT,
+    created_on TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Names that are replaced by their tag when questions are asked or edited.
+CREATE TABLE IF NOT EXISTS tag_synonyms (
+    name TEXT PRIMARY KEY,
+    tag_id integer NOT NULL REFERENCES tags ON DELETE CASCADE
+);
+
+-- Same as tag::normalize: lower case, runs of whitespace and underscores become a dash and the
+-- ends are trimmed of dashes. Names that end up the same are merged.
+UPDATE questions SET tags = COALESCE((
+    SELECT array_agg(name ORDER BY ord) FROM (
+        SELECT name, MIN(ord) AS ord FROM (
+            SELECT btrim(regexp_replace(lower(trim(tag)), '[[:space:]_]+', '-', 'g'), '-') AS name, ord
+            FROM unnest(tags) WITH ORDINALITY AS tag_names(tag, ord)
+        ) normalized
+        WHERE name <> ''
+        GROUP BY name
+    ) deduplicated
+), '{}')
+WHERE tags IS NOT NULL;
+
+INSERT INTO tags (name)
+SELECT DISTINCT unnest(tags) FROM questions
+ON CONFLICT (name) DO NOTHING;
+
+CREATE INDEX IF NOT EXISTS questions_tags ON questions USING GIN (tags);
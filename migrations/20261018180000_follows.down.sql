@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS answers_question_id;
+DROP TABLE IF EXISTS question_follows;
+DROP TABLE IF EXISTS tag_follows;
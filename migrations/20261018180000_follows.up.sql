@@ -0,0 +1,17 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS tag_follows (
+    account_id integer NOT NULL,
+    tag_id integer NOT NULL REFERENCES tags ON DELETE CASCADE,
+    created_on TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (account_id, tag_id)
+);
+
+CREATE TABLE IF NOT EXISTS question_follows (
+    account_id integer NOT NULL,
+    question_id integer NOT NULL REFERENCES questions ON DELETE CASCADE,
+    created_on TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (account_id, question_id)
+);
+
+-- The feed looks up answers by question.
+CREATE INDEX IF NOT EXISTS answers_question_id ON answers (question_id);
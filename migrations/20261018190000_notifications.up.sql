@@ -0,0 +1,14 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS notifications (
+    id serial PRIMARY KEY,
+    account_id integer NOT NULL,
+    kind TEXT NOT NULL CHECK (kind IN ('answer', 'mention')),
+    question_id integer NOT NULL REFERENCES questions ON DELETE CASCADE,
+    answer_id integer REFERENCES answers ON DELETE CASCADE,
+    -- The account whose question or answer caused it.
+    actor_id integer NOT NULL,
+    read_at TIMESTAMPTZ,
+    created_on TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS notifications_account_id ON notifications (account_id, id);
@@ -0,0 +1,7 @@
+-- Add down migration script here
+DROP TRIGGER IF EXISTS notifications_notify_event ON notifications;
+DROP FUNCTION IF EXISTS notify_notification_event;
+DROP TRIGGER IF EXISTS answers_notify_event ON answers;
+DROP FUNCTION IF EXISTS notify_answer_event;
+DROP TRIGGER IF EXISTS questions_notify_event ON questions;
+DROP FUNCTION IF EXISTS notify_question_event;
@@ -0,0 +1,68 @@
+-- Add up migration script here
+-- Changes are sent on the book_events channel, so every server instance can pass them on to its
+-- clients whichever instance made them. Payloads only carry ids, NOTIFY limits them to 8000 bytes
+-- and a question's tags can be longer. events::listen looks up the tags.
+
+CREATE OR REPLACE FUNCTION notify_question_event() RETURNS trigger AS $$
+DECLARE
+    kind TEXT;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        kind := 'question_created';
+    ELSIF NEW.deleted_at IS NOT NULL AND OLD.deleted_at IS NULL THEN
+        kind := 'question_deleted';
+    ELSIF NEW.deleted_at IS NULL THEN
+        -- Edits and restores.
+        kind := 'question_updated';
+    ELSE
+        RETURN NULL;
+    END IF;
+    PERFORM pg_notify('book_events', json_build_object(
+        'kind', kind,
+        'question_id', NEW.id
+    )::text);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER questions_notify_event
+    AFTER INSERT OR UPDATE ON questions
+    FOR EACH ROW EXECUTE FUNCTION notify_question_event();
+
+CREATE OR REPLACE FUNCTION notify_answer_event() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('book_events', json_build_object(
+        'kind', 'answer_created',
+        'question_id', NEW.question_id,
+        'answer_id', NEW.id
+    )::text);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER answers_notify_event
+    AFTER INSERT ON answers
+    FOR EACH ROW EXECUTE FUNCTION notify_answer_event();
+
+CREATE OR REPLACE FUNCTION notify_notification_event() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('book_events', json_build_object(
+        'kind', 'notification',
+        'account_id', NEW.account_id,
+        'notification', json_build_object(
+            'id', NEW.id,
+            'kind', NEW.kind,
+            'question_id', NEW.question_id,
+            'answer_id', NEW.answer_id,
+            'actor', NEW.actor_id,
+            'read_at', NEW.read_at,
+            'created_on', NEW.created_on
+        )
+    )::text);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER notifications_notify_event
+    AFTER INSERT ON notifications
+    FOR EACH ROW EXECUTE FUNCTION notify_notification_event();
@@ -0,0 +1,699 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use clap::Parser;
+use figment::providers::{Env, Format, Serialized, Toml};
+use figment::Figment;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use tracing_subscriber::EnvFilter;
+
+use crate::oidc::Provider;
+use crate::password::HashParams;
+use crate::rate_limit::Limit;
+use crate::telemetry::LogFormat;
+
+/// Read when no `--config` is given, if it exists.
+const DEFAULT_CONFIG_FILE: &str = "book.toml";
+
+/// Password of the local dev database, as the server has always defaulted to.
+const DEFAULT_DATABASE_PASSWORD: &str = "admin1";
+
+/// Environment variables we've always read, and the config keys they set.
+///
+/// Any other key can be set with a `BOOK_` prefixed variable, using `__` between sections,
+/// e.g. `BOOK_DATABASE__MAX_CONNECTIONS=10`.
+const ENV_KEYS: &[(&str, &str)] = &[
+    ("PORT", "server.port"),
+    ("METRICS_ADDR", "server.metrics_addr"),
+    ("PASETO_KEY", "auth.paseto_key"),
+    ("ARGON2_MEMORY_KIB", "auth.argon2.memory_kib"),
+    ("ARGON2_ITERATIONS", "auth.argon2.iterations"),
+    ("ARGON2_PARALLELISM", "auth.argon2.parallelism"),
+    ("BAD_WORDS_API_KEY", "content_filter.api_key"),
+    ("OIDC_ISSUER_URL", "oidc.issuer"),
+    ("OIDC_CLIENT_ID", "oidc.client_id"),
+    ("OIDC_CLIENT_SECRET", "oidc.client_secret"),
+    ("OIDC_REDIRECT_URL", "oidc.redirect_url"),
+    ("RUST_LOG", "log.filter"),
+    ("OTEL_EXPORTER_OTLP_ENDPOINT", "log.otlp_endpoint"),
+];
+
+/// Q&A web service API
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about=None)]
+pub struct Args {
+    /// TOML config file, `book.toml` is read by default if it exists
+    #[clap(short, long, env = "BOOK_CONFIG")]
+    config: Option<PathBuf>,
+    /// Print the configuration in effect with secrets masked, and exit
+    #[clap(long)]
+    pub print_config: bool,
+    /// Address to listen on
+    #[clap(long)]
+    host: Option<IpAddr>,
+    /// Port to listen on
+    #[clap(short, long)]
+    port: Option<u16>,
+    /// Unix socket to listen on instead of host and port
+    #[clap(long)]
+    unix_socket: Option<PathBuf>,
+    /// Which errors to log (info warn or error)
+    #[clap(short, long)]
+    log_level: Option<String>,
+    /// Log level for a single target, e.g. `book::store=debug`. Can be repeated.
+    #[clap(long = "log-target")]
+    log_targets: Vec<String>,
+    /// Log output format
+    #[clap(long, value_enum)]
+    log_format: Option<LogFormat>,
+    /// Write logs to daily rotated files in this directory instead of stdout
+    #[clap(long)]
+    log_dir: Option<String>,
+    /// URL for the postgres DB
+    #[clap(long)]
+    database_host: Option<String>,
+    /// PORT number for DB.
+    #[clap(long)]
+    database_port: Option<u16>,
+    /// Database name
+    #[clap(long)]
+    database_name: Option<String>,
+}
+
+impl Args {
+    /// Overrides config with the flags that were given.
+    fn apply(&self, config: &mut Config) {
+        if let Some(host) = self.host {
+            config.server.host = host;
+        }
+        if let Some(port) = self.port {
+            config.server.port = port;
+        }
+        if let Some(path) = &self.unix_socket {
+            config.server.unix_socket = Some(path.clone());
+        }
+        if let Some(level) = &self.log_level {
+            config.log.level = level.clone();
+        }
+        if !self.log_targets.is_empty() {
+            config.log.targets = self.log_targets.clone();
+        }
+        // A filter, e.g. from RUST_LOG, would replace the level and targets given here.
+        if self.log_level.is_some() || !self.log_targets.is_empty() {
+            config.log.filter = None;
+        }
+        if let Some(format) = self.log_format {
+            config.log.format = format;
+        }
+        if let Some(dir) = &self.log_dir {
+            config.log.dir = Some(dir.clone());
+        }
+        if let Some(host) = &self.database_host {
+            config.database.host = host.clone();
+        }
+        if let Some(port) = self.database_port {
+            config.database.port = port;
+        }
+        if let Some(name) = &self.database_name {
+            config.database.name = Some(name.clone());
+        }
+    }
+}
+
+/// A value that must not end up in logs or `--print-config` output.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[redacted]")
+    }
+}
+
+/// Serializes masked, so printing the config only shows whether a secret is set.
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.0.is_empty() {
+            serializer.serialize_str("")
+        } else {
+            serializer.serialize_str("********")
+        }
+    }
+}
+
+/// Accepts numbers and booleans too, environment variables holding e.g. an all digit password
+/// come in as numbers.
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct SecretVisitor;
+
+        impl<'de> de::Visitor<'de> for SecretVisitor {
+            type Value = Secret;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a string")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Secret, E> {
+                Ok(Secret(v.to_string()))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Secret, E> {
+                Ok(Secret(v.to_string()))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Secret, E> {
+                Ok(Secret(v.to_string()))
+            }
+
+            fn visit_f64<E: de::Error>(self, v: f64) -> Result<Secret, E> {
+                Ok(Secret(v.to_string()))
+            }
+
+            fn visit_bool<E: de::Error>(self, v: bool) -> Result<Secret, E> {
+                Ok(Secret(v.to_string()))
+            }
+        }
+
+        deserializer.deserialize_any(SecretVisitor)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub auth: AuthConfig,
+    pub content_filter: ContentFilterConfig,
+    /// SSO is disabled unless this is set.
+    pub oidc: Option<Provider>,
+    pub rate_limit: RateLimitConfig,
+    pub deletion: DeletionConfig,
+    pub log: LogConfig,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub host: IpAddr,
+    pub port: u16,
+    /// Listen on this Unix socket instead of host and port.
+    pub unix_socket: Option<PathBuf>,
+    /// Serve `/metrics` on its own address instead of the main one.
+    pub metrics_addr: Option<SocketAddr>,
+    /// How long requests in flight get to finish on shutdown.
+    pub shutdown_timeout_secs: u64,
+    /// Serve HTTPS instead of HTTP.
+    pub tls: Option<TlsConfig>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            host: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 3031,
+            unix_socket: None,
+            metrics_addr: None,
+            shutdown_timeout_secs: 30,
+            tls: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn addr(&self) -> SocketAddr {
+        SocketAddr::new(self.host, self.port)
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout_secs)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// PEM encoded certificate chain, reloaded on SIGHUP along with the key.
+    pub cert: PathBuf,
+    /// PEM encoded private key.
+    pub key: PathBuf,
+    /// Redirect plain HTTP requests on this address to HTTPS.
+    pub redirect_from: Option<SocketAddr>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    pub password: Secret,
+    /// Defaults to the user name, like other postgres clients.
+    pub name: Option<String>,
+    pub max_connections: u32,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            host: "localhost".to_string(),
+            port: 9003,
+            user: "postgres".to_string(),
+            password: Secret(DEFAULT_DATABASE_PASSWORD.to_string()),
+            name: None,
+            max_connections: 5,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Symmetric key for PASETO v2 local tokens, 32 bytes.
+    pub paseto_key: Secret,
+    /// Cost of new password hashes.
+    pub argon2: HashParams,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ContentFilterConfig {
+    pub url: String,
+    pub api_key: Secret,
+}
+
+impl Default for ContentFilterConfig {
+    fn default() -> Self {
+        ContentFilterConfig {
+            url: "https://api.apilayer.com".to_string(),
+            api_key: Secret::default(),
+        }
+    }
+}
+
+/// Where rate limit buckets are kept.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitBackend {
+    /// Per process, so each replica allows the full rate.
+    Memory,
+    /// Shared by all replicas, at the cost of a query per request.
+    Postgres,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    pub backend: RateLimitBackend,
+    /// Reading questions, API keys and sessions.
+    pub read: Limit,
+    /// Adding, changing and deleting anything.
+    pub write: Limit,
+    /// Per IP, since there's no account yet.
+    pub registration: Limit,
+    /// Per IP, on top of the lockout after failed logins.
+    pub login: Limit,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            enabled: true,
+            backend: RateLimitBackend::Memory,
+            read: Limit {
+                burst: 60,
+                per_minute: 300,
+            },
+            write: Limit {
+                burst: 20,
+                per_minute: 60,
+            },
+            registration: Limit {
+                burst: 3,
+                per_minute: 5,
+            },
+            login: Limit {
+                burst: 10,
+                per_minute: 20,
+            },
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeletionConfig {
+    /// How long deleted questions and answers can be restored.
+    pub restore_window_days: u32,
+    /// How long deleted questions and answers are kept before they're purged, at least the
+    /// restore window so nothing restorable is purged.
+    pub retention_days: u32,
+}
+
+impl Default for DeletionConfig {
+    fn default() -> Self {
+        DeletionConfig {
+            restore_window_days: 30,
+            retention_days: 90,
+        }
+    }
+}
+
+impl DeletionConfig {
+    pub fn restore_window(&self) -> Duration {
+        Duration::from_secs(u64::from(self.restore_window_days) * 24 * 60 * 60)
+    }
+
+    pub fn retention(&self) -> Duration {
+        Duration::from_secs(u64::from(self.retention_days) * 24 * 60 * 60)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// Level for our own crates and warp.
+    pub level: String,
+    /// Per target levels on top of level, e.g. `book::store=debug`.
+    pub targets: Vec<String>,
+    /// Complete `EnvFilter` directives, replacing level and targets.
+    pub filter: Option<String>,
+    pub format: LogFormat,
+    /// Write daily rotated files in this directory instead of stdout.
+    pub dir: Option<String>,
+    /// Export spans over OTLP/HTTP to the collector at this URL, e.g. `http://localhost:4318`.
+    /// Like with `OTEL_EXPORTER_OTLP_ENDPOINT`, `/v1/traces` is appended unless it has a path.
+    pub otlp_endpoint: Option<String>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            level: "warn".to_string(),
+            targets: vec![],
+            filter: None,
+            format: LogFormat::Text,
+            dir: None,
+            otlp_endpoint: None,
+        }
+    }
+}
+
+impl LogConfig {
+    /// The URL spans are sent to, if they're exported.
+    pub fn otlp_traces_url(&self) -> Option<String> {
+        let endpoint = self.otlp_endpoint.as_ref()?;
+        match reqwest::Url::parse(endpoint) {
+            Ok(url) if url.path() == "/" => {
+                Some(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+            }
+            _ => Some(endpoint.clone()),
+        }
+    }
+
+    /// Which traces to record, in `EnvFilter` syntax.
+    pub fn filter(&self) -> String {
+        if let Some(filter) = &self.filter {
+            return filter.clone();
+        }
+        let mut filter = format!(
+            "handle_errors={},book={},warp={}",
+            self.level, self.level, self.level
+        );
+        for target in &self.targets {
+            filter.push(',');
+            filter.push_str(target);
+        }
+        filter
+    }
+}
+
+impl Config {
+    /// Loads the config from, in increasing order of precedence, defaults, the config file,
+    /// environment variables and args.
+    ///
+    /// Returns every problem found rather than just the first.
+    pub fn load(args: &Args) -> Result<Config, Vec<String>> {
+        let file = match &args.config {
+            Some(path) if !path.is_file() => {
+                return Err(vec![format!("config file {} not found", path.display())])
+            }
+            Some(path) => path.clone(),
+            None => PathBuf::from(DEFAULT_CONFIG_FILE),
+        };
+
+        let mut config: Config = Figment::from(Serialized::defaults(Config::default()))
+            // Secrets serialize masked, so the default password is set again in the clear.
+            .merge(Serialized::default(
+                "database.password",
+                DEFAULT_DATABASE_PASSWORD,
+            ))
+            .merge(Toml::file(file))
+            .merge(Env::raw().filter_map(|key| {
+                ENV_KEYS
+                    .iter()
+                    .find(|(name, _)| key == *name)
+                    .map(|(_, path)| (*path).into())
+            }))
+            .merge(Env::prefixed("BOOK_").ignore(&["config"]).split("__"))
+            .extract()
+            .map_err(|e| e.into_iter().map(|e| e.to_string()).collect::<Vec<_>>())?;
+
+        args.apply(&mut config);
+        if let Some(oidc) = &mut config.oidc {
+            oidc.issuer = oidc.issuer.trim_end_matches('/').to_string();
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = vec![];
+
+        if self.server.unix_socket.is_some() && self.server.tls.is_some() {
+            errors.push("server.tls can't be used with server.unix_socket".to_string());
+        }
+
+        if self.database.host.is_empty() {
+            errors.push("database.host must be set".to_string());
+        }
+        if self.database.max_connections == 0 {
+            errors.push("database.max_connections must be at least 1".to_string());
+        }
+
+        if self.auth.paseto_key.is_empty() {
+            errors.push("auth.paseto_key (PASETO_KEY) must be set".to_string());
+        } else if self.auth.paseto_key.expose().len() != 32 {
+            errors.push("auth.paseto_key (PASETO_KEY) must be exactly 32 bytes".to_string());
+        }
+        let argon2 = &self.auth.argon2;
+        if argon2.lanes == 0 {
+            errors.push("auth.argon2.parallelism must be at least 1".to_string());
+        }
+        if argon2.time_cost == 0 {
+            errors.push("auth.argon2.iterations must be at least 1".to_string());
+        }
+        if argon2.mem_cost < 8 * argon2.lanes {
+            errors.push(
+                "auth.argon2.memory_kib must be at least 8 times auth.argon2.parallelism"
+                    .to_string(),
+            );
+        }
+
+        if self.content_filter.api_key.is_empty() {
+            errors.push("content_filter.api_key (BAD_WORDS_API_KEY) must be set".to_string());
+        }
+        if reqwest::Url::parse(&self.content_filter.url).is_err() {
+            errors.push("content_filter.url must be a URL".to_string());
+        }
+
+        if let Some(oidc) = &self.oidc {
+            if reqwest::Url::parse(&oidc.issuer).is_err() {
+                errors.push("oidc.issuer (OIDC_ISSUER_URL) must be a URL".to_string());
+            }
+            if reqwest::Url::parse(&oidc.redirect_url).is_err() {
+                errors.push("oidc.redirect_url (OIDC_REDIRECT_URL) must be a URL".to_string());
+            }
+        }
+
+        let rate_limit = &self.rate_limit;
+        for (name, limit) in [
+            ("read", rate_limit.read),
+            ("write", rate_limit.write),
+            ("registration", rate_limit.registration),
+            ("login", rate_limit.login),
+        ] {
+            if limit.burst == 0 || limit.per_minute == 0 {
+                errors.push(format!(
+                    "rate_limit.{} burst and per_minute must be at least 1",
+                    name
+                ));
+            }
+        }
+
+        if self.deletion.restore_window_days > self.deletion.retention_days {
+            errors.push(format!(
+                "deletion.restore_window_days ({}) is longer than deletion.retention_days ({}), \
+                 deleted content would be purged while it can still be restored",
+                self.deletion.restore_window_days, self.deletion.retention_days
+            ));
+        }
+
+        if let Some(endpoint) = &self.log.otlp_endpoint {
+            match reqwest::Url::parse(endpoint) {
+                Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+                _ => errors.push(
+                    "log.otlp_endpoint (OTEL_EXPORTER_OTLP_ENDPOINT) must be an http(s) URL"
+                        .to_string(),
+                ),
+            }
+        }
+
+        if let Err(e) = EnvFilter::try_new(self.log.filter()) {
+            errors.push(format!("invalid log filter {:?}: {}", self.log.filter(), e));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Renders the config as TOML, with secrets masked.
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).expect("config can be represented as TOML")
+    }
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Makes config available through `get`, has to be called once at startup.
+pub fn init(config: Config) -> &'static Config {
+    CONFIG.get_or_init(|| config)
+}
+
+pub fn get() -> &'static Config {
+    CONFIG.get().expect("config::init wasn't called")
+}
+
+#[cfg(test)]
+// Jail closures return figment's error, which is large.
+#[allow(clippy::result_large_err)]
+mod tests {
+    use figment::Jail;
+
+    use super::*;
+
+    const PASETO_KEY: &str = "0123456789abcdef0123456789abcdef";
+
+    /// Sets what has to be set for a config to load.
+    fn set_required(jail: &mut Jail) {
+        jail.set_env("PASETO_KEY", PASETO_KEY);
+        jail.set_env("BAD_WORDS_API_KEY", "key");
+    }
+
+    fn load(args: &[&str]) -> Result<Config, Vec<String>> {
+        Config::load(&Args::parse_from([&["book"], args].concat()))
+    }
+
+    #[test]
+    fn args_override_env_override_the_file() {
+        Jail::expect_with(|jail| {
+            set_required(jail);
+            jail.create_file("book.toml", "[server]\nport = 1000\n")?;
+            assert_eq!(load(&[]).unwrap().server.port, 1000);
+
+            jail.set_env("BOOK_SERVER__PORT", 2000);
+            assert_eq!(load(&[]).unwrap().server.port, 2000);
+
+            assert_eq!(load(&["--port", "3000"]).unwrap().server.port, 3000);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn prefixed_env_overrides_legacy_names() {
+        Jail::expect_with(|jail| {
+            set_required(jail);
+            jail.set_env("PORT", 4000);
+            let config = load(&[]).unwrap();
+            assert_eq!(config.server.port, 4000);
+            assert_eq!(config.auth.paseto_key.expose(), PASETO_KEY);
+
+            jail.set_env("BOOK_SERVER__PORT", 5000);
+            let key = PASETO_KEY.to_uppercase();
+            jail.set_env("BOOK_AUTH__PASETO_KEY", &key);
+            let config = load(&[]).unwrap();
+            assert_eq!(config.server.port, 5000);
+            assert_eq!(config.auth.paseto_key.expose(), key);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn log_args_replace_the_filter() {
+        Jail::expect_with(|jail| {
+            set_required(jail);
+            jail.set_env("RUST_LOG", "debug");
+            assert_eq!(load(&[]).unwrap().log.filter(), "debug");
+
+            let config = load(&["--log-level", "info"]).unwrap();
+            assert_eq!(
+                config.log.filter(),
+                "handle_errors=info,book=info,warp=info"
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn rejects_a_paseto_key_of_the_wrong_length() {
+        Jail::expect_with(|jail| {
+            set_required(jail);
+            jail.set_env("PASETO_KEY", "too short");
+            let errors = load(&[]).unwrap_err();
+            assert_eq!(
+                errors,
+                ["auth.paseto_key (PASETO_KEY) must be exactly 32 bytes"]
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn rejects_a_retention_shorter_than_the_restore_window() {
+        Jail::expect_with(|jail| {
+            set_required(jail);
+            jail.create_file(
+                "book.toml",
+                "[deletion]\nrestore_window_days = 30\nretention_days = 7\n",
+            )?;
+            let errors = load(&[]).unwrap_err();
+            assert_eq!(errors.len(), 1);
+            assert!(errors[0].starts_with("deletion.restore_window_days (30)"));
+
+            jail.create_file(
+                "book.toml",
+                "[deletion]\nrestore_window_days = 30\nretention_days = 30\n",
+            )?;
+            assert!(load(&[]).is_ok());
+            Ok(())
+        });
+    }
+}
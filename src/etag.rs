@@ -0,0 +1,122 @@
+use sha2::{Digest, Sha256};
+use warp::http::{HeaderValue, StatusCode};
+use warp::Reply;
+
+/// ETag of a single resource at version.
+pub fn of_version(version: i32) -> String {
+    format!("\"{}\"", version)
+}
+
+/// ETag of a list of resources, changing whenever one is added, removed or updated.
+pub fn of_versions(items: impl IntoIterator<Item = (i32, i32)>) -> String {
+    let mut hash = Sha256::new();
+    for (id, version) in items {
+        hash.update(format!("{}:{},", id, version));
+    }
+    format!("\"{:x}\"", hash.finalize())
+}
+
+/// Returns the versions an `If-Match` header allows, or None if it allows any (`*`).
+///
+/// Weak tags never match, so the result can be empty.
+pub fn versions_in(if_match: &str) -> Option<Vec<i32>> {
+    if if_match.trim() == "*" {
+        return None;
+    }
+    Some(
+        if_match
+            .split(',')
+            .filter_map(|tag| {
+                tag.trim()
+                    .strip_prefix('"')?
+                    .strip_suffix('"')?
+                    .parse()
+                    .ok()
+            })
+            .collect(),
+    )
+}
+
+/// Returns true if an `If-None-Match` header matches etag, comparing weakly.
+fn none_match_hits(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// Adds etag to reply, or replies 304 Not Modified if the client already has it.
+pub fn reply(
+    reply: impl Reply,
+    etag: &str,
+    if_none_match: Option<String>,
+) -> warp::reply::Response {
+    let mut res = match if_none_match {
+        Some(tags) if none_match_hits(&tags, etag) => StatusCode::NOT_MODIFIED.into_response(),
+        _ => reply.into_response(),
+    };
+    if let Ok(etag) = HeaderValue::from_str(etag) {
+        res.headers_mut().insert("ETag", etag);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn if_match_star_allows_any_version() {
+        assert_eq!(versions_in("*"), None);
+        assert_eq!(versions_in(" * "), None);
+    }
+
+    #[test]
+    fn if_match_lists_versions() {
+        assert_eq!(versions_in("\"3\""), Some(vec![3]));
+        assert_eq!(versions_in("\"3\",\"5\""), Some(vec![3, 5]));
+        assert_eq!(versions_in(" \"3\" ,  \"5\" "), Some(vec![3, 5]));
+    }
+
+    #[test]
+    fn if_match_skips_weak_and_malformed_tags() {
+        assert_eq!(versions_in("W/\"3\""), Some(vec![]));
+        assert_eq!(versions_in("3"), Some(vec![]));
+        assert_eq!(versions_in("\"3"), Some(vec![]));
+        assert_eq!(versions_in("\"abc\", \"\", \"4\""), Some(vec![4]));
+        assert_eq!(versions_in("*, \"4\""), Some(vec![4]));
+        assert_eq!(versions_in(""), Some(vec![]));
+    }
+
+    #[test]
+    fn if_none_match_compares_weakly() {
+        let etag = of_version(3);
+        assert!(none_match_hits("\"3\"", &etag));
+        assert!(none_match_hits("W/\"3\"", &etag));
+        assert!(none_match_hits("*", &etag));
+        assert!(none_match_hits("\"1\", W/\"2\",  \"3\"", &etag));
+    }
+
+    #[test]
+    fn if_none_match_misses_other_and_malformed_tags() {
+        let etag = of_version(3);
+        assert!(!none_match_hits("\"4\"", &etag));
+        assert!(!none_match_hits("3", &etag));
+        assert!(!none_match_hits("\"03\"", &etag));
+        assert!(!none_match_hits("\"abc\"", &etag));
+        assert!(!none_match_hits("", &etag));
+    }
+
+    #[test]
+    fn replies_304_only_on_a_hit() {
+        let etag = of_version(3);
+        let res = reply("body", &etag, Some("W/\"3\"".to_string()));
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(res.headers()["ETag"], "\"3\"");
+
+        let res = reply("body", &etag, Some("\"2\"".to_string()));
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = reply("body", &etag, None);
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
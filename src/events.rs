@@ -0,0 +1,130 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+use sqlx::postgres::PgListener;
+use tokio::sync::broadcast;
+
+use crate::store::Store;
+use crate::types::account::AccountId;
+use crate::types::answer::AnswerId;
+use crate::types::event::Event;
+use crate::types::notification::Notification;
+use crate::types::question::QuestionId;
+
+/// Channel the triggers in the events migration notify on.
+const CHANNEL: &str = "book_events";
+/// Events kept for subscribers that fall behind, older ones are dropped for them.
+const CAPACITY: usize = 1024;
+/// How long to wait before listening again after the connection failed.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// What the triggers send, only ids as NOTIFY payloads are limited to 8000 bytes. The tags of
+/// questions are looked up when they're received.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Payload {
+    QuestionCreated {
+        question_id: QuestionId,
+    },
+    QuestionUpdated {
+        question_id: QuestionId,
+    },
+    QuestionDeleted {
+        question_id: QuestionId,
+    },
+    AnswerCreated {
+        question_id: QuestionId,
+        answer_id: AnswerId,
+    },
+    Notification {
+        account_id: AccountId,
+        notification: Notification,
+    },
+}
+
+impl Payload {
+    async fn into_event(self, store: &Store) -> Result<Event, handle_errors::Error> {
+        Ok(match self {
+            Payload::QuestionCreated { question_id } => Event::QuestionCreated {
+                tags: store.get_question_tags(question_id.0).await?,
+                question_id,
+            },
+            Payload::QuestionUpdated { question_id } => Event::QuestionUpdated {
+                tags: store.get_question_tags(question_id.0).await?,
+                question_id,
+            },
+            Payload::QuestionDeleted { question_id } => Event::QuestionDeleted {
+                tags: store.get_question_tags(question_id.0).await?,
+                question_id,
+            },
+            Payload::AnswerCreated {
+                question_id,
+                answer_id,
+            } => Event::AnswerCreated {
+                tags: store.get_question_tags(question_id.0).await?,
+                question_id,
+                answer_id,
+            },
+            Payload::Notification {
+                account_id,
+                notification,
+            } => Event::Notification {
+                account_id,
+                notification,
+            },
+        })
+    }
+}
+
+/// Passes changes on to the clients connected to this instance, whichever instance made them.
+#[derive(Clone)]
+pub struct Events {
+    sender: broadcast::Sender<Event>,
+}
+
+impl Events {
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+}
+
+/// Starts listening on the `book_events` channel.
+pub fn spawn(store: Store) -> Events {
+    let (sender, _) = broadcast::channel(CAPACITY);
+    let events = Events {
+        sender: sender.clone(),
+    };
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = listen(&store, &sender).await {
+                // The pool is closed on shutdown.
+                if store.connection.is_closed() {
+                    return;
+                }
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+            }
+            tokio::time::sleep(RETRY_DELAY).await;
+        }
+    });
+    events
+}
+
+async fn listen(store: &Store, sender: &broadcast::Sender<Event>) -> Result<(), sqlx::Error> {
+    let mut listener = PgListener::connect_with(&store.connection).await?;
+    listener.listen(CHANNEL).await?;
+    loop {
+        let notification = listener.recv().await?;
+        let payload = match serde_json::from_str::<Payload>(notification.payload()) {
+            Ok(payload) => payload,
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                continue;
+            }
+        };
+        // The store logs its errors, the event is dropped then.
+        if let Ok(event) = payload.into_event(store).await {
+            // Fails when nobody is subscribed, which is fine.
+            let _ = sender.send(event);
+        }
+    }
+}
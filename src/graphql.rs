@@ -0,0 +1,482 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_graphql::dataloader::{DataLoader, Loader};
+use async_graphql::{Context, EmptySubscription, ErrorExtensions, InputObject, Object, Schema};
+use handle_errors::Error;
+
+use crate::rate_limit::{Class, RateLimiter};
+use crate::routes::answer::create_answer;
+use crate::routes::question::{change_question, create_question};
+use crate::store::Store;
+use crate::types::account::{AccountId, Session};
+use crate::types::answer::{Answer, NewAnswer};
+use crate::types::api_key::Scope;
+use crate::types::question::{NewQuestion, Question, QuestionId};
+
+pub type BookSchema = Schema<Query, Mutation, EmptySubscription>;
+
+/// Builds the schema served at `/graphql`.
+///
+/// Requests need a `Session` if authenticated and a loader from `loader` added as data.
+pub fn schema(store: Store, limiter: RateLimiter) -> BookSchema {
+    Schema::build(Query, Mutation, EmptySubscription)
+        .data(store)
+        .data(limiter)
+        .limit_depth(10)
+        .limit_complexity(500)
+        .finish()
+}
+
+/// Returns a loader batching the lookups of one request.
+///
+/// It caches what it loaded, so it must not be shared between requests.
+pub fn loader(store: Store) -> DataLoader<StoreLoader> {
+    DataLoader::new(StoreLoader(store), tokio::spawn)
+}
+
+/// Turns errors of the REST handlers into GraphQL errors with a `code` extension.
+///
+/// Like `return_error`, details of internal errors are only logged.
+fn graphql_error(e: &Error) -> async_graphql::Error {
+    let (code, message) = match e {
+        Error::MissingCredentials
+        | Error::CannotDecryptToken
+        | Error::SessionRevoked
+        | Error::InvalidApiKey => ("UNAUTHENTICATED", e.to_string()),
+        Error::Unauthorized => ("FORBIDDEN", e.to_string()),
+        Error::NotFound(_) => ("NOT_FOUND", e.to_string()),
+        Error::RateLimited(_) => ("RATE_LIMITED", e.to_string()),
+        Error::PreconditionFailed => ("CONFLICT", e.to_string()),
+        Error::ContentFilterUnavailable => ("UNAVAILABLE", e.to_string()),
+        Error::DatabaseQueryError(s) => ("INTERNAL", s.clone()),
+        _ => {
+            tracing::event!(tracing::Level::ERROR, "{}", e);
+            ("INTERNAL", "Internal Server Error".to_string())
+        }
+    };
+    async_graphql::Error::new(message).extend_with(|_, ext| {
+        ext.set("code", code);
+        if let Error::RateLimited(limit) = e {
+            ext.set("retryAfter", limit.retry_after);
+        }
+    })
+}
+
+fn store<'a>(ctx: &Context<'a>) -> &'a Store {
+    ctx.data_unchecked::<Store>()
+}
+
+fn loader_of<'a>(ctx: &Context<'a>) -> &'a DataLoader<StoreLoader> {
+    ctx.data_unchecked::<DataLoader<StoreLoader>>()
+}
+
+/// Returns the session of the request, or fails if it wasn't authenticated.
+fn session<'a>(ctx: &Context<'a>) -> async_graphql::Result<&'a Session> {
+    ctx.data_opt::<Session>()
+        .ok_or_else(|| graphql_error(&Error::MissingCredentials))
+}
+
+/// Key for the answers to a question.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AnswersTo(QuestionId);
+
+/// Key for the questions asked by an account.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct QuestionsBy(AccountId);
+
+/// Key for the answers written by an account.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AnswersBy(AccountId);
+
+/// Loads everything a query can nest with one DB query per kind of field and level.
+pub struct StoreLoader(Store);
+
+impl Loader<QuestionId> for StoreLoader {
+    type Value = QuestionNode;
+    type Error = Arc<Error>;
+
+    async fn load(
+        &self,
+        keys: &[QuestionId],
+    ) -> Result<HashMap<QuestionId, QuestionNode>, Self::Error> {
+        let ids: Vec<i32> = keys.iter().map(|k| k.0).collect();
+        let questions = self.0.get_questions_by_ids(&ids).await?;
+        Ok(questions
+            .into_iter()
+            .map(|(question, author)| (question.id.clone(), QuestionNode { question, author }))
+            .collect())
+    }
+}
+
+impl Loader<AnswersTo> for StoreLoader {
+    type Value = Vec<AnswerNode>;
+    type Error = Arc<Error>;
+
+    async fn load(
+        &self,
+        keys: &[AnswersTo],
+    ) -> Result<HashMap<AnswersTo, Vec<AnswerNode>>, Self::Error> {
+        let ids: Vec<i32> = keys.iter().map(|k| k.0 .0).collect();
+        let mut answers: HashMap<AnswersTo, Vec<AnswerNode>> = HashMap::new();
+        for (answer, author) in self.0.get_answers_by_question_ids(&ids).await? {
+            answers
+                .entry(AnswersTo(answer.question_id.clone()))
+                .or_default()
+                .push(AnswerNode { answer, author });
+        }
+        Ok(answers)
+    }
+}
+
+impl Loader<AccountId> for StoreLoader {
+    type Value = AccountNode;
+    type Error = Arc<Error>;
+
+    async fn load(
+        &self,
+        keys: &[AccountId],
+    ) -> Result<HashMap<AccountId, AccountNode>, Self::Error> {
+        let ids: Vec<i32> = keys.iter().map(|k| k.0).collect();
+        let accounts = self.0.get_account_emails(&ids).await?;
+        Ok(accounts
+            .into_iter()
+            .map(|(id, email)| (id.clone(), AccountNode { id, email }))
+            .collect())
+    }
+}
+
+impl Loader<QuestionsBy> for StoreLoader {
+    type Value = Vec<QuestionNode>;
+    type Error = Arc<Error>;
+
+    async fn load(
+        &self,
+        keys: &[QuestionsBy],
+    ) -> Result<HashMap<QuestionsBy, Vec<QuestionNode>>, Self::Error> {
+        let ids: Vec<i32> = keys.iter().map(|k| k.0 .0).collect();
+        let mut questions: HashMap<QuestionsBy, Vec<QuestionNode>> = HashMap::new();
+        for (question, author) in self.0.get_questions_by_account_ids(&ids).await? {
+            questions
+                .entry(QuestionsBy(author.clone()))
+                .or_default()
+                .push(QuestionNode { question, author });
+        }
+        Ok(questions)
+    }
+}
+
+impl Loader<AnswersBy> for StoreLoader {
+    type Value = Vec<AnswerNode>;
+    type Error = Arc<Error>;
+
+    async fn load(
+        &self,
+        keys: &[AnswersBy],
+    ) -> Result<HashMap<AnswersBy, Vec<AnswerNode>>, Self::Error> {
+        let ids: Vec<i32> = keys.iter().map(|k| k.0 .0).collect();
+        let mut answers: HashMap<AnswersBy, Vec<AnswerNode>> = HashMap::new();
+        for (answer, author) in self.0.get_answers_by_account_ids(&ids).await? {
+            answers
+                .entry(AnswersBy(author.clone()))
+                .or_default()
+                .push(AnswerNode { answer, author });
+        }
+        Ok(answers)
+    }
+}
+
+/// A question and the account that asked it.
+#[derive(Debug, Clone)]
+pub struct QuestionNode {
+    question: Question,
+    author: AccountId,
+}
+
+#[Object(name = "Question")]
+impl QuestionNode {
+    async fn id(&self) -> i32 {
+        self.question.id.0
+    }
+
+    async fn title(&self) -> &str {
+        &self.question.title
+    }
+
+    async fn content(&self) -> &str {
+        &self.question.content
+    }
+
+    async fn tags(&self) -> &Option<Vec<String>> {
+        &self.question.tags
+    }
+
+    /// Bumped on every update, see `updateQuestion`.
+    async fn version(&self) -> i32 {
+        self.question.version
+    }
+
+    async fn author(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<AccountNode>> {
+        loader_of(ctx)
+            .load_one(self.author.clone())
+            .await
+            .map_err(|e| graphql_error(&e))
+    }
+
+    /// Answers, oldest first.
+    async fn answers(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<AnswerNode>> {
+        let answers = loader_of(ctx)
+            .load_one(AnswersTo(self.question.id.clone()))
+            .await
+            .map_err(|e| graphql_error(&e))?;
+        Ok(answers.unwrap_or_default())
+    }
+}
+
+/// An answer and the account that wrote it.
+#[derive(Debug, Clone)]
+pub struct AnswerNode {
+    answer: Answer,
+    author: AccountId,
+}
+
+#[Object(name = "Answer")]
+impl AnswerNode {
+    async fn id(&self) -> i32 {
+        self.answer.id.0
+    }
+
+    async fn content(&self) -> &str {
+        &self.answer.content
+    }
+
+    async fn question(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<QuestionNode>> {
+        loader_of(ctx)
+            .load_one(self.answer.question_id.clone())
+            .await
+            .map_err(|e| graphql_error(&e))
+    }
+
+    async fn author(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<AccountNode>> {
+        loader_of(ctx)
+            .load_one(self.author.clone())
+            .await
+            .map_err(|e| graphql_error(&e))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AccountNode {
+    id: AccountId,
+    email: String,
+}
+
+#[Object(name = "Account")]
+impl AccountNode {
+    async fn id(&self) -> i32 {
+        self.id.0
+    }
+
+    /// Only visible to the account itself, or its API keys with the read scope.
+    async fn email(&self, ctx: &Context<'_>) -> Option<&str> {
+        match ctx.data_opt::<Session>() {
+            Some(session) if session.account_id == self.id && session.allows(Scope::Read) => {
+                Some(&self.email)
+            }
+            _ => None,
+        }
+    }
+
+    /// Questions asked, oldest first.
+    async fn questions(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<QuestionNode>> {
+        let questions = loader_of(ctx)
+            .load_one(QuestionsBy(self.id.clone()))
+            .await
+            .map_err(|e| graphql_error(&e))?;
+        Ok(questions.unwrap_or_default())
+    }
+
+    /// Answers written, oldest first.
+    async fn answers(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<AnswerNode>> {
+        let answers = loader_of(ctx)
+            .load_one(AnswersBy(self.id.clone()))
+            .await
+            .map_err(|e| graphql_error(&e))?;
+        Ok(answers.unwrap_or_default())
+    }
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    /// Questions ordered by id, paginated like `GET /v2/questions`.
+    async fn questions(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<i32>,
+        #[graphql(default = 0)] offset: i32,
+    ) -> async_graphql::Result<Vec<QuestionNode>> {
+        let questions = store(ctx)
+            .get_questions_with_authors(limit, offset)
+            .await
+            .map_err(|e| graphql_error(&e))?;
+        Ok(questions
+            .into_iter()
+            .map(|(question, author)| QuestionNode { question, author })
+            .collect())
+    }
+
+    async fn question(
+        &self,
+        ctx: &Context<'_>,
+        id: i32,
+    ) -> async_graphql::Result<Option<QuestionNode>> {
+        loader_of(ctx)
+            .load_one(QuestionId(id))
+            .await
+            .map_err(|e| graphql_error(&e))
+    }
+
+    async fn account(
+        &self,
+        ctx: &Context<'_>,
+        id: i32,
+    ) -> async_graphql::Result<Option<AccountNode>> {
+        loader_of(ctx)
+            .load_one(AccountId(id))
+            .await
+            .map_err(|e| graphql_error(&e))
+    }
+
+    /// The account the request is authenticated as.
+    async fn me(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<AccountNode>> {
+        let session = session(ctx)?;
+        if !session.allows(Scope::Read) {
+            return Err(graphql_error(&Error::Unauthorized));
+        }
+        loader_of(ctx)
+            .load_one(session.account_id.clone())
+            .await
+            .map_err(|e| graphql_error(&e))
+    }
+}
+
+#[derive(InputObject)]
+pub struct QuestionInput {
+    title: String,
+    content: String,
+    tags: Option<Vec<String>>,
+}
+
+pub struct Mutation;
+
+#[Object]
+impl Mutation {
+    /// Like `POST /v2/questions`, bad words in the title and content are censored.
+    async fn add_question(
+        &self,
+        ctx: &Context<'_>,
+        input: QuestionInput,
+    ) -> async_graphql::Result<QuestionNode> {
+        let session = session(ctx)?;
+        ctx.data_unchecked::<RateLimiter>()
+            .check_account(Class::Write, &session.account_id)
+            .await
+            .map_err(|e| graphql_error(&e))?;
+        let new_question = NewQuestion {
+            title: input.title,
+            content: input.content,
+            tags: input.tags,
+        };
+        let question = create_question(session, store(ctx), new_question)
+            .await
+            .map_err(|e| graphql_error(&e))?;
+        Ok(QuestionNode {
+            question,
+            author: session.account_id.clone(),
+        })
+    }
+
+    /// Like `PUT /v2/questions/{id}`, only the account that asked may update a question.
+    ///
+    /// With expected_version it's only updated if it's still at that version. The summary is
+    /// kept in the revision of the update.
+    async fn update_question(
+        &self,
+        ctx: &Context<'_>,
+        id: i32,
+        input: QuestionInput,
+        expected_version: Option<i32>,
+        summary: Option<String>,
+    ) -> async_graphql::Result<QuestionNode> {
+        let session = session(ctx)?;
+        ctx.data_unchecked::<RateLimiter>()
+            .check_account(Class::Write, &session.account_id)
+            .await
+            .map_err(|e| graphql_error(&e))?;
+        let question = Question {
+            id: QuestionId(id),
+            title: input.title,
+            content: input.content,
+            tags: input.tags,
+            version: 0,
+        };
+        let versions = expected_version.map(|version| vec![version]);
+        let question = change_question(session, store(ctx), id, question, versions, summary)
+            .await
+            .map_err(|e| graphql_error(&e))?;
+        Ok(QuestionNode {
+            question,
+            author: session.account_id.clone(),
+        })
+    }
+
+    /// Like `DELETE /v2/questions/{id}`, returns the id of the deleted question.
+    async fn delete_question(&self, ctx: &Context<'_>, id: i32) -> async_graphql::Result<i32> {
+        let session = session(ctx)?;
+        ctx.data_unchecked::<RateLimiter>()
+            .check_account(Class::Write, &session.account_id)
+            .await
+            .map_err(|e| graphql_error(&e))?;
+        let store = store(ctx);
+        if !session.is_login()
+            || !store
+                .is_question_owner(id, &session.account_id)
+                .await
+                .map_err(|e| graphql_error(&e))?
+        {
+            return Err(graphql_error(&Error::Unauthorized));
+        }
+
+        match store.delete_question(id, &session.account_id).await {
+            None => Ok(id),
+            Some(e) => Err(graphql_error(&e)),
+        }
+    }
+
+    /// Like `POST /v2/answers`, bad words in the content are censored.
+    async fn add_answer(
+        &self,
+        ctx: &Context<'_>,
+        question_id: i32,
+        content: String,
+    ) -> async_graphql::Result<AnswerNode> {
+        let session = session(ctx)?;
+        ctx.data_unchecked::<RateLimiter>()
+            .check_account(Class::Write, &session.account_id)
+            .await
+            .map_err(|e| graphql_error(&e))?;
+        let new_answer = NewAnswer {
+            content,
+            question_id: QuestionId(question_id),
+        };
+        let answer = create_answer(session, store(ctx), new_answer)
+            .await
+            .map_err(|e| graphql_error(&e))?;
+        Ok(AnswerNode {
+            answer,
+            author: session.account_id.clone(),
+        })
+    }
+}
@@ -1,104 +1,143 @@
 #![warn(clippy::all)]
 
+mod config;
+mod etag;
+mod events;
+mod graphql;
+mod metrics;
+mod oidc;
+mod openapi;
+mod password;
 mod profanity;
+mod purge;
+mod rate_limit;
 mod routes;
+mod server;
 mod store;
+mod telemetry;
+mod tls;
 mod types;
+mod versioning;
 
-use std::env;
+#[cfg(test)]
+#[path = "../examples/mock_oidc/provider.rs"]
+mod mock_oidc;
 
+use std::process;
+
+use clap::Parser;
+use config::{Args, Config};
 use handle_errors::return_error;
+use rate_limit::{Class, RateLimiter};
 use store::Store;
-use tracing_subscriber::fmt::format::FmtSpan;
-use warp::{http::Method, Filter};
+use warp::{http::Method, Filter, Reply};
 
-use clap::Parser;
-use dotenv;
-
-/// Q&A web service API
-#[derive(Parser, Debug)]
-#[clap(author, version, about, long_about=None)]
-struct Args {
-    /// Which errors to log (info warn or error)
-    #[clap(short, long, default_value = "warn")]
-    log_level: String,
-    /// URL for the postgres DB
-    #[clap(long, default_value = "localhost")]
-    database_host: String,
-    /// PORT number for DB.
-    #[clap(long, default_value = "9003")]
-    database_port: u16,
-    /// Database name
-    #[clap(long, default_value = "")]
-    database_name: String,
+/// Prints why the server can't start and exits.
+fn exit_with(errors: &[String]) -> ! {
+    eprintln!("Cannot start:");
+    for error in errors {
+        eprintln!("  - {}", error);
+    }
+    process::exit(1);
 }
 
 #[tokio::main]
 async fn main() {
     dotenv::dotenv().ok();
-    if let Err(_) = env::var("BAD_WORDS_API_KEY") {
-        panic!("BadWords API key not set");
-    }
-    if let Err(_) = env::var("PASETO_KEY") {
-        panic!("PASETO key not set");
+    let args = Args::parse();
+    let config = match Config::load(&args) {
+        Ok(config) => config::init(config),
+        Err(errors) => exit_with(&errors),
+    };
+    if args.print_config {
+        print!("{}", config.to_toml());
+        return;
     }
 
-    let port = std::env::var("PORT")
-        .ok()
-        .map(|v| v.parse::<u16>())
-        .unwrap_or(Ok(3031));
+    // First, so connecting to the DB and starting the background tasks are logged too. Keep the
+    // guard alive so buffered log lines get written on exit.
+    let _log_guard = match telemetry::init(&config.log) {
+        Ok(guard) => guard,
+        Err(e) => exit_with(&[e]),
+    };
 
-    let args = Args::parse();
-    let log_filter = std::env::var("RUST_LOG").unwrap_or_else(|_| {
-        format!(
-            "handle_errors={},book={},warp={}",
-            args.log_level, args.log_level, args.log_level
-        )
-    });
-
-    let store = Store::new(&format!(
-        "postgres://postgres:admin1@{}:{}",
-        args.database_host, args.database_port
-    ))
-    .await;
-
-    sqlx::migrate!()
-        .run(&store.clone().connection)
-        .await
-        .expect("Cannot run migration");
+    let tls = match &config.server.tls {
+        Some(tls) => match tls::Acceptor::new(tls) {
+            Ok(acceptor) => Some(acceptor),
+            Err(e) => exit_with(&[e]),
+        },
+        None => None,
+    };
 
-    let store_filter = warp::any().map(move || store.clone());
+    let store = match Store::new(&config.database).await {
+        Ok(store) => store,
+        Err(e) => exit_with(&[format!("cannot connect to the DB: {}", e)]),
+    };
+
+    if let Err(e) = store::MIGRATOR.run(&store.connection).await {
+        exit_with(&[format!("cannot run migrations: {}", e)]);
+    }
 
-    tracing_subscriber::fmt()
-        // Use the filter we built above to determine which traces to record
-        .with_env_filter(log_filter)
-        // Record an event when each span closes, this can be used for routes
-        // duration.
-        .with_span_events(FmtSpan::CLOSE)
-        .init();
+    // Kept to close the pool on shutdown, store is moved into the filters.
+    let pool = store.connection.clone();
+    let auth = routes::authentication::auth(store.clone());
+    let optional_auth = routes::authentication::optional_auth(store.clone());
+    let limiter = RateLimiter::new(&config.rate_limit, store.clone());
+    purge::spawn(&config.deletion, store.clone());
+    let events = events::spawn(store.clone());
+    let events_filter = warp::any().map(move || events.clone());
+    let shutdown = server::Shutdown::listen();
+    let shutdown_filter = {
+        let shutdown = shutdown.clone();
+        warp::any().map(move || shutdown.clone())
+    };
+    let schema = graphql::schema(store.clone(), limiter.clone());
+    let store_filter = warp::any().map(move || store.clone());
 
     let cors = warp::cors()
         .allow_any_origin()
-        .allow_header("content-type")
-        .allow_methods(&[Method::PUT, Method::DELETE, Method::GET, Method::POST]);
+        .allow_headers(vec![
+            "content-type",
+            "authorization",
+            "if-match",
+            "if-none-match",
+            "x-api-key",
+            "x-request-id",
+            "traceparent",
+        ])
+        .allow_methods(&[
+            Method::PUT,
+            Method::PATCH,
+            Method::DELETE,
+            Method::GET,
+            Method::POST,
+        ])
+        .expose_headers(vec![
+            "location",
+            "etag",
+            "deprecation",
+            "sunset",
+            "link",
+            "x-request-id",
+            "x-ratelimit-limit",
+            "x-ratelimit-remaining",
+            "x-ratelimit-reset",
+            "retry-after",
+        ]);
 
     let get_questions = warp::get()
         .and(warp::path("questions"))
         .and(warp::path::end())
+        .and(limiter.by_ip(Class::Read))
         .and(warp::query())
+        .and(warp::header::optional::<String>("if-none-match"))
         .and(store_filter.clone())
-        .and_then(routes::question::get_questions)
-        .with(warp::trace(|info| {
-            tracing::info_span!("get questions request",
-                method = %info.method(),
-                path = %info.path(),
-                id = %uuid::Uuid::new_v4(),)
-        }));
+        .and_then(routes::question::get_questions);
 
     let add_question = warp::post()
         .and(warp::path("questions"))
         .and(warp::path::end())
-        .and(routes::authentication::auth())
+        .and(limiter.by_account(Class::Write, auth.clone()))
         .and(store_filter.clone())
         .and(warp::body::json())
         .and_then(routes::question::add_question);
@@ -106,31 +145,271 @@ async fn main() {
     let add_answer = warp::post()
         .and(warp::path("answers"))
         .and(warp::path::end())
-        .and(routes::authentication::auth())
+        .and(limiter.by_account(Class::Write, auth.clone()))
         .and(store_filter.clone())
         .and(warp::body::json())
         .and_then(routes::answer::add_answer);
 
+    let add_question_v2 = warp::post()
+        .and(warp::path("questions"))
+        .and(warp::path::end())
+        .and(limiter.by_account(Class::Write, auth.clone()))
+        .and(store_filter.clone())
+        .and(warp::body::json())
+        .and_then(routes::v2::add_question);
+
+    let add_answer_v2 = warp::post()
+        .and(warp::path("answers"))
+        .and(warp::path::end())
+        .and(limiter.by_account(Class::Write, auth.clone()))
+        .and(store_filter.clone())
+        .and(warp::body::json())
+        .and_then(routes::v2::add_answer);
+
+    let get_question_v2 = warp::get()
+        .and(warp::path("questions"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::end())
+        .and(limiter.by_ip(Class::Read))
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and(store_filter.clone())
+        .and_then(routes::v2::get_question);
+
+    let get_answer_v2 = warp::get()
+        .and(warp::path("answers"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::end())
+        .and(limiter.by_ip(Class::Read))
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and(store_filter.clone())
+        .and_then(routes::v2::get_answer);
+
     let update_question = warp::put()
         .and(warp::path("questions"))
         .and(warp::path::param::<i32>())
         .and(warp::path::end())
-        .and(routes::authentication::auth())
+        .and(limiter.by_account(Class::Write, auth.clone()))
         .and(store_filter.clone())
+        .and(warp::header::optional::<String>("if-match"))
         .and(warp::body::json())
         .and_then(routes::question::update_question);
 
+    let update_question_v2 = warp::put()
+        .and(warp::path("questions"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::end())
+        .and(limiter.by_account(Class::Write, auth.clone()))
+        .and(store_filter.clone())
+        .and(warp::header::optional::<String>("if-match"))
+        .and(warp::body::json())
+        .and_then(routes::v2::update_question);
+
+    let patch_question_v2 = warp::patch()
+        .and(warp::path("questions"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::end())
+        .and(limiter.by_account(Class::Write, auth.clone()))
+        .and(store_filter.clone())
+        .and(warp::header::optional::<String>("if-match"))
+        .and(routes::v2::merge_patch())
+        .and_then(routes::v2::patch_question);
+
+    let get_revisions = warp::get()
+        .and(warp::path("questions"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("revisions"))
+        .and(warp::path::end())
+        .and(limiter.by_ip(Class::Read))
+        .and(store_filter.clone())
+        .and_then(routes::revision::get_revisions);
+
+    let rollback_question = warp::post()
+        .and(warp::path("questions"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("revisions"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("rollback"))
+        .and(warp::path::end())
+        .and(limiter.by_account(Class::Write, auth.clone()))
+        .and(store_filter.clone())
+        .and(warp::header::optional::<String>("if-match"))
+        .and_then(routes::revision::rollback_question);
+
     let delete_question = warp::delete()
         .and(warp::path("questions"))
         .and(warp::path::param::<i32>())
         .and(warp::path::end())
-        .and(routes::authentication::auth())
+        .and(limiter.by_account(Class::Write, auth.clone()))
         .and(store_filter.clone())
         .and_then(routes::question::delete_question);
 
+    let restore_question = warp::post()
+        .and(warp::path("questions"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("restore"))
+        .and(warp::path::end())
+        .and(limiter.by_account(Class::Write, auth.clone()))
+        .and(store_filter.clone())
+        .and_then(routes::question::restore_question);
+
+    let delete_answer = warp::delete()
+        .and(warp::path("answers"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::end())
+        .and(limiter.by_account(Class::Write, auth.clone()))
+        .and(store_filter.clone())
+        .and_then(routes::answer::delete_answer);
+
+    let restore_answer = warp::post()
+        .and(warp::path("answers"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("restore"))
+        .and(warp::path::end())
+        .and(limiter.by_account(Class::Write, auth.clone()))
+        .and(store_filter.clone())
+        .and_then(routes::answer::restore_answer);
+
+    let get_follows = warp::get()
+        .and(warp::path("accounts"))
+        .and(warp::path("me"))
+        .and(warp::path("follows"))
+        .and(warp::path::end())
+        .and(limiter.by_account(Class::Read, auth.clone()))
+        .and(store_filter.clone())
+        .and_then(routes::follow::get_follows);
+
+    let follow_tag = warp::put()
+        .and(warp::path("accounts"))
+        .and(warp::path("me"))
+        .and(warp::path("follows"))
+        .and(warp::path("tags"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(limiter.by_account(Class::Write, auth.clone()))
+        .and(store_filter.clone())
+        .and_then(routes::follow::follow_tag);
+
+    let unfollow_tag = warp::delete()
+        .and(warp::path("accounts"))
+        .and(warp::path("me"))
+        .and(warp::path("follows"))
+        .and(warp::path("tags"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(limiter.by_account(Class::Write, auth.clone()))
+        .and(store_filter.clone())
+        .and_then(routes::follow::unfollow_tag);
+
+    let follow_question = warp::put()
+        .and(warp::path("accounts"))
+        .and(warp::path("me"))
+        .and(warp::path("follows"))
+        .and(warp::path("questions"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::end())
+        .and(limiter.by_account(Class::Write, auth.clone()))
+        .and(store_filter.clone())
+        .and_then(routes::follow::follow_question);
+
+    let unfollow_question = warp::delete()
+        .and(warp::path("accounts"))
+        .and(warp::path("me"))
+        .and(warp::path("follows"))
+        .and(warp::path("questions"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::end())
+        .and(limiter.by_account(Class::Write, auth.clone()))
+        .and(store_filter.clone())
+        .and_then(routes::follow::unfollow_question);
+
+    let get_feed = warp::get()
+        .and(warp::path("feed"))
+        .and(warp::path::end())
+        .and(warp::query())
+        .and(limiter.by_account(Class::Read, auth.clone()))
+        .and(store_filter.clone())
+        .and_then(routes::follow::get_feed);
+
+    let get_notifications = warp::get()
+        .and(warp::path("accounts"))
+        .and(warp::path("me"))
+        .and(warp::path("notifications"))
+        .and(warp::path::end())
+        .and(warp::query())
+        .and(limiter.by_account(Class::Read, auth.clone()))
+        .and(store_filter.clone())
+        .and_then(routes::notification::get_notifications);
+
+    let get_unread_count = warp::get()
+        .and(warp::path("accounts"))
+        .and(warp::path("me"))
+        .and(warp::path("notifications"))
+        .and(warp::path("unread_count"))
+        .and(warp::path::end())
+        .and(limiter.by_account(Class::Read, auth.clone()))
+        .and(store_filter.clone())
+        .and_then(routes::notification::get_unread_count);
+
+    let mark_notification_read = warp::post()
+        .and(warp::path("accounts"))
+        .and(warp::path("me"))
+        .and(warp::path("notifications"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("read"))
+        .and(warp::path::end())
+        .and(limiter.by_account(Class::Write, auth.clone()))
+        .and(store_filter.clone())
+        .and_then(routes::notification::mark_read);
+
+    let mark_all_notifications_read = warp::post()
+        .and(warp::path("accounts"))
+        .and(warp::path("me"))
+        .and(warp::path("notifications"))
+        .and(warp::path("read"))
+        .and(warp::path::end())
+        .and(limiter.by_account(Class::Write, auth.clone()))
+        .and(store_filter.clone())
+        .and_then(routes::notification::mark_all_read);
+
+    let get_tags = warp::get()
+        .and(warp::path("tags"))
+        .and(warp::path::end())
+        .and(limiter.by_ip(Class::Read))
+        .and(warp::query())
+        .and(store_filter.clone())
+        .and_then(routes::tag::get_tags);
+
+    let get_tag = warp::get()
+        .and(warp::path("tags"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(limiter.by_ip(Class::Read))
+        .and(store_filter.clone())
+        .and_then(routes::tag::get_tag);
+
+    let update_tag = warp::put()
+        .and(warp::path("tags"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(limiter.by_account(Class::Write, auth.clone()))
+        .and(store_filter.clone())
+        .and(warp::body::json())
+        .and_then(routes::tag::update_tag);
+
+    let merge_tag = warp::post()
+        .and(warp::path("tags"))
+        .and(warp::path::param::<String>())
+        .and(warp::path("merge"))
+        .and(warp::path::end())
+        .and(limiter.by_account(Class::Write, auth.clone()))
+        .and(store_filter.clone())
+        .and(warp::body::json())
+        .and_then(routes::tag::merge_tag);
+
     let registration = warp::post()
         .and(warp::path("registration"))
         .and(warp::path::end())
+        .and(limiter.by_ip(Class::Registration))
         .and(store_filter.clone())
         .and(warp::body::json())
         .and_then(routes::authentication::register);
@@ -138,20 +417,299 @@ async fn main() {
     let login = warp::post()
         .and(warp::path("login"))
         .and(warp::path::end())
+        .and(limiter.by_ip(Class::Login))
         .and(store_filter.clone())
+        .and(server::remote())
+        .and(warp::header::optional::<String>("user-agent"))
         .and(warp::body::json())
         .and_then(routes::authentication::login);
 
-    let routes = get_questions
+    let registration_v2 = warp::post()
+        .and(warp::path("registration"))
+        .and(warp::path::end())
+        .and(limiter.by_ip(Class::Registration))
+        .and(store_filter.clone())
+        .and(warp::body::json())
+        .and_then(routes::v2::register);
+
+    let get_account_v2 = warp::get()
+        .and(warp::path("accounts"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::end())
+        .and(limiter.by_account(Class::Read, auth.clone()))
+        .and(store_filter.clone())
+        .and_then(routes::v2::get_account);
+
+    let oidc_login = warp::get()
+        .and(warp::path("oidc"))
+        .and(warp::path("login"))
+        .and(warp::path::end())
+        .and(limiter.by_ip(Class::Login))
+        .and(store_filter.clone())
+        .and_then(routes::oidc::oidc_login);
+
+    let oidc_callback = warp::get()
+        .and(warp::path("oidc"))
+        .and(warp::path("callback"))
+        .and(warp::path::end())
+        .and(limiter.by_ip(Class::Login))
+        .and(store_filter.clone())
+        .and(server::remote())
+        .and(warp::header::optional::<String>("user-agent"))
+        .and(warp::cookie::optional::<String>("oidc_state"))
+        .and(warp::query())
+        .and_then(routes::oidc::oidc_callback);
+
+    let add_api_key = warp::post()
+        .and(warp::path("accounts"))
+        .and(warp::path("me"))
+        .and(warp::path("api_keys"))
+        .and(warp::path::end())
+        .and(limiter.by_account(Class::Write, auth.clone()))
+        .and(store_filter.clone())
+        .and(warp::body::json())
+        .and_then(routes::api_key::add_api_key);
+
+    let get_api_keys = warp::get()
+        .and(warp::path("accounts"))
+        .and(warp::path("me"))
+        .and(warp::path("api_keys"))
+        .and(warp::path::end())
+        .and(limiter.by_account(Class::Read, auth.clone()))
+        .and(store_filter.clone())
+        .and_then(routes::api_key::get_api_keys);
+
+    let delete_api_key = warp::delete()
+        .and(warp::path("accounts"))
+        .and(warp::path("me"))
+        .and(warp::path("api_keys"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::end())
+        .and(limiter.by_account(Class::Write, auth.clone()))
+        .and(store_filter.clone())
+        .and_then(routes::api_key::delete_api_key);
+
+    let get_sessions = warp::get()
+        .and(warp::path("accounts"))
+        .and(warp::path("me"))
+        .and(warp::path("sessions"))
+        .and(warp::path::end())
+        .and(limiter.by_account(Class::Read, auth.clone()))
+        .and(store_filter.clone())
+        .and_then(routes::session::get_sessions);
+
+    let delete_session = warp::delete()
+        .and(warp::path("accounts"))
+        .and(warp::path("me"))
+        .and(warp::path("sessions"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::end())
+        .and(limiter.by_account(Class::Write, auth.clone()))
+        .and(store_filter.clone())
+        .and_then(routes::session::delete_session);
+
+    let events = warp::get()
+        .and(warp::path("events"))
+        .and(warp::path::end())
+        .and(limiter.by_ip(Class::Read))
+        .and(warp::query())
+        .and(optional_auth.clone())
+        .and(store_filter.clone())
+        .and(events_filter.clone())
+        .and(shutdown_filter.clone())
+        .and_then(routes::event::events);
+
+    let events_ws = warp::get()
+        .and(warp::path("events"))
+        .and(warp::path("ws"))
+        .and(warp::path::end())
+        .and(limiter.by_ip(Class::Read))
+        .and(warp::ws())
+        .and(warp::query())
+        .and(optional_auth.clone())
+        .and(store_filter.clone())
+        .and(events_filter)
+        .and(shutdown_filter)
+        .and_then(routes::event::events_ws);
+
+    let graphql = warp::post()
+        .and(warp::path("graphql"))
+        .and(warp::path::end())
+        .and(limiter.by_ip(Class::Read))
+        .and(optional_auth.clone())
+        .and(store_filter.clone())
+        .and(warp::any().map(move || schema.clone()))
+        .and(warp::body::json())
+        .and_then(routes::graphql::graphql);
+
+    let metrics_route = warp::get()
+        .and(warp::path("metrics"))
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and_then(metrics::metrics);
+
+    let healthz = warp::get()
+        .and(warp::path("healthz"))
+        .and(warp::path::end())
+        .and_then(routes::health::healthz);
+
+    let readyz = warp::get()
+        .and(warp::path("readyz"))
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and_then(routes::health::readyz);
+
+    let openapi_json = warp::get()
+        .and(warp::path("openapi.json"))
+        .and(warp::path::end())
+        .and_then(routes::docs::openapi_json);
+
+    let docs = warp::get()
+        .and(warp::path("docs"))
+        .and(warp::path::full())
+        .and(warp::path::tail())
+        .and_then(routes::docs::swagger_ui);
+
+    // Frozen, changes to replies go into v2.
+    let v1 = get_questions
+        .clone()
         .or(add_question)
         .or(add_answer)
         .or(update_question)
-        .or(delete_question)
+        .or(delete_question.clone())
         .or(registration)
+        .or(login.clone())
+        .or(oidc_login.clone())
+        .or(oidc_callback.clone())
+        .or(add_api_key.clone())
+        .or(get_api_keys.clone())
+        .or(delete_api_key.clone())
+        .or(get_sessions.clone())
+        .or(delete_session.clone())
+        .map(Reply::into_response)
+        .boxed();
+
+    // Split in two boxed halves, as one chain this long overflows the stack in debug builds.
+    let v2_content = get_questions
+        .or(get_question_v2)
+        .or(add_question_v2)
+        .or(get_answer_v2)
+        .or(add_answer_v2)
+        .or(update_question_v2)
+        .or(patch_question_v2)
+        .or(get_revisions)
+        .or(rollback_question)
+        .or(delete_question)
+        .or(restore_question)
+        .or(delete_answer)
+        .or(restore_answer)
+        .or(get_tags)
+        .or(get_tag)
+        .or(update_tag)
+        .or(merge_tag)
+        .or(get_feed)
+        .or(events)
+        .or(events_ws)
+        .map(Reply::into_response)
+        .boxed();
+
+    let v2_accounts = registration_v2
+        .or(get_account_v2)
         .or(login)
+        .or(oidc_login)
+        .or(oidc_callback)
+        .or(add_api_key)
+        .or(get_api_keys)
+        .or(delete_api_key)
+        .or(get_sessions)
+        .or(delete_session)
+        .or(get_follows)
+        .or(follow_tag)
+        .or(unfollow_tag)
+        .or(follow_question)
+        .or(unfollow_question)
+        .or(get_notifications)
+        .or(get_unread_count)
+        .or(mark_notification_read)
+        .or(mark_all_notifications_read)
+        .map(Reply::into_response)
+        .boxed();
+
+    let v2 = v2_content.or(v2_accounts).map(Reply::into_response).boxed();
+
+    let routes = warp::path("v1")
+        .and(v1.clone())
+        .or(warp::path("v2").and(v2))
+        // Root aliases of v1, see `versioning::deprecate_root_aliases`.
+        .or(v1)
+        .or(graphql)
+        .or(healthz)
+        .or(readyz)
+        .or(openapi_json)
+        .or(docs)
         .with(cors)
-        .with(warp::trace::request())
         .recover(return_error);
+    let routes = warp::path::full()
+        .and(routes)
+        .map(versioning::deprecate_root_aliases)
+        .with(metrics::requests());
+
+    let addr = config.server.addr();
+    let listener = match (&config.server.unix_socket, tls) {
+        (Some(path), _) => server::Listener::Unix(path.clone()),
+        (None, Some(tls)) => {
+            tls.reload_on_sighup();
+            server::Listener::Tls(addr, tls)
+        }
+        (None, None) => server::Listener::Tcp(addr),
+    };
+    let drain = config.server.shutdown_timeout();
+
+    if let Some(redirect_from) = config.server.tls.as_ref().and_then(|t| t.redirect_from) {
+        let redirect = server::serve(
+            warp::service(server::redirect_to_https(config.server.port)),
+            server::Listener::Tcp(redirect_from),
+            shutdown.clone(),
+            drain,
+        );
+        tokio::spawn(async move {
+            if let Err(e) = redirect.await {
+                exit_with(&[e.to_string()]);
+            }
+        });
+    }
+
+    // Metrics can be served on their own address to keep them off the public port.
+    let served = match config.server.metrics_addr {
+        Some(metrics_addr) => {
+            let metrics_server = server::serve(
+                warp::service(metrics_route),
+                server::Listener::Tcp(metrics_addr),
+                shutdown.clone(),
+                drain,
+            );
+            tokio::spawn(async move {
+                if let Err(e) = metrics_server.await {
+                    exit_with(&[e.to_string()]);
+                }
+            });
+            server::serve(warp::service(routes), listener, shutdown, drain).await
+        }
+        None => {
+            server::serve(
+                warp::service(metrics_route.or(routes)),
+                listener,
+                shutdown,
+                drain,
+            )
+            .await
+        }
+    };
+    if let Err(e) = served {
+        exit_with(&[e.to_string()]);
+    }
 
-    warp::serve(routes).run(([127, 0, 0, 1], 3031)).await;
+    tracing::event!(tracing::Level::INFO, "closing DB connections");
+    pool.close().await;
+    telemetry::shutdown();
 }
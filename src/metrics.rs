@@ -0,0 +1,204 @@
+use std::sync::OnceLock;
+
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+
+use crate::openapi;
+use crate::store::Store;
+
+/// Everything we export to Prometheus.
+pub struct Metrics {
+    registry: Registry,
+    pub http_requests: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+    pub db_pool_connections: IntGauge,
+    pub db_pool_idle: IntGauge,
+    pub db_pool_max: IntGauge,
+    pub profanity_duration: Histogram,
+    pub profanity_errors: IntCounter,
+    pub profanity_retries: IntCounter,
+    pub logins: IntCounterVec,
+    pub rate_limited: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+        let metrics = Metrics {
+            http_requests: IntCounterVec::new(
+                Opts::new("http_requests_total", "HTTP requests by route and status"),
+                &["method", "route", "status"],
+            )
+            .unwrap(),
+            http_request_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "http_request_duration_seconds",
+                    "HTTP request latency by route and status",
+                ),
+                &["method", "route", "status"],
+            )
+            .unwrap(),
+            db_pool_connections: IntGauge::new(
+                "db_pool_connections",
+                "Open connections in the DB pool",
+            )
+            .unwrap(),
+            db_pool_idle: IntGauge::new(
+                "db_pool_idle_connections",
+                "Idle connections in the DB pool",
+            )
+            .unwrap(),
+            db_pool_max: IntGauge::new("db_pool_max_connections", "Size limit of the DB pool")
+                .unwrap(),
+            profanity_duration: Histogram::with_opts(HistogramOpts::new(
+                "profanity_check_duration_seconds",
+                "Latency of content filter API calls, including retries",
+            ))
+            .unwrap(),
+            profanity_errors: IntCounter::new(
+                "profanity_check_errors_total",
+                "Content filter API calls that failed",
+            )
+            .unwrap(),
+            profanity_retries: IntCounter::new(
+                "profanity_check_retries_total",
+                "Content filter API requests that were retries",
+            )
+            .unwrap(),
+            logins: IntCounterVec::new(
+                Opts::new("logins_total", "Login attempts by result"),
+                &["result"],
+            )
+            .unwrap(),
+            rate_limited: IntCounterVec::new(
+                Opts::new(
+                    "rate_limited_requests_total",
+                    "Requests rejected by rate limits, by limit",
+                ),
+                &["class"],
+            )
+            .unwrap(),
+            registry,
+        };
+
+        let r = &metrics.registry;
+        r.register(Box::new(metrics.http_requests.clone())).unwrap();
+        r.register(Box::new(metrics.http_request_duration.clone()))
+            .unwrap();
+        r.register(Box::new(metrics.db_pool_connections.clone()))
+            .unwrap();
+        r.register(Box::new(metrics.db_pool_idle.clone())).unwrap();
+        r.register(Box::new(metrics.db_pool_max.clone())).unwrap();
+        r.register(Box::new(metrics.profanity_duration.clone()))
+            .unwrap();
+        r.register(Box::new(metrics.profanity_errors.clone()))
+            .unwrap();
+        r.register(Box::new(metrics.profanity_retries.clone()))
+            .unwrap();
+        r.register(Box::new(metrics.logins.clone())).unwrap();
+        r.register(Box::new(metrics.rate_limited.clone())).unwrap();
+        metrics
+    }
+}
+
+pub fn get() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::new)
+}
+
+/// Templates of the documented routes split into segments, e.g. `["", "v2", "questions", "{id}"]`,
+/// the ones with the fewest params first so literal segments win.
+fn templates() -> &'static [Vec<String>] {
+    static TEMPLATES: OnceLock<Vec<Vec<String>>> = OnceLock::new();
+    TEMPLATES.get_or_init(|| {
+        let mut templates: Vec<Vec<String>> = openapi::document()
+            .paths
+            .paths
+            .keys()
+            .map(|path| path.split('/').map(str::to_string).collect())
+            .collect();
+        templates.sort_by_key(|template| template.iter().filter(|s| is_param(s)).count());
+        templates
+    })
+}
+
+fn is_param(segment: &str) -> bool {
+    segment.starts_with('{') && segment.ends_with('}')
+}
+
+/// Returns the template of the documented route path matches.
+fn matching_template(path: &str) -> Option<String> {
+    let segments: Vec<&str> = path.split('/').collect();
+    templates()
+        .iter()
+        .find(|template| {
+            template.len() == segments.len()
+                && template
+                    .iter()
+                    .zip(&segments)
+                    .all(|(t, s)| is_param(t) || t == s)
+        })
+        .map(|template| template.join("/"))
+}
+
+/// Turns a request path into the template of its route, e.g. `/v2/questions/12` into
+/// `/v2/questions/{id}`.
+///
+/// Labels only come from the routes in the OpenAPI document, so random URLs can't blow up the
+/// number of series: paths that match none are all labeled `unmatched`.
+fn route_label(path: &str) -> String {
+    if path == "/docs" || path.starts_with("/docs/") {
+        return "/docs".to_string();
+    }
+    if let Some(template) = matching_template(path) {
+        return template;
+    }
+    // The root aliases of /v1 aren't documented.
+    match matching_template(&format!("/v1{}", path)) {
+        Some(template) => template["/v1".len()..].to_string(),
+        None => "unmatched".to_string(),
+    }
+}
+
+/// Records count and latency of every request, to be wrapped around all routes.
+pub fn requests() -> warp::log::Log<impl Fn(warp::log::Info) + Copy> {
+    warp::log::custom(|info| {
+        let route = route_label(info.path());
+        let status = info.status();
+        let labels = [info.method().as_str(), &route, status.as_str()];
+        let m = get();
+        m.http_requests.with_label_values(&labels).inc();
+        m.http_request_duration
+            .with_label_values(&labels)
+            .observe(info.elapsed().as_secs_f64());
+    })
+}
+
+/// Handler for `/metrics`, in the Prometheus text format.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "operations",
+    responses((status = 200, description = "Metrics in the Prometheus text format", body = String, content_type = "text/plain"))
+)]
+pub async fn metrics(store: Store) -> Result<impl warp::Reply, warp::Rejection> {
+    let m = get();
+    let pool = &store.connection;
+    m.db_pool_connections.set(pool.size() as i64);
+    m.db_pool_idle.set(pool.num_idle() as i64);
+    m.db_pool_max
+        .set(pool.options().get_max_connections() as i64);
+
+    let mut buffer = vec![];
+    let encoder = TextEncoder::new();
+    if let Err(e) = encoder.encode(&m.registry.gather(), &mut buffer) {
+        tracing::event!(tracing::Level::ERROR, "failed to encode metrics: {}", e);
+    }
+    Ok(warp::reply::with_header(
+        buffer,
+        "content-type",
+        encoder.format_type(),
+    ))
+}
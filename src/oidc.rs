@@ -0,0 +1,254 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use handle_errors::Error;
+use rand::{distributions::Alphanumeric, Rng};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::OnceCell;
+
+use crate::config::{self, Secret};
+
+/// OIDC provider used for single sign-on.
+///
+/// `redirect_url` has to point at our `/v2/oidc/callback` route, `client_secret` can be left
+/// out for public clients.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Provider {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<Secret>,
+    pub redirect_url: String,
+}
+
+/// Returns the configured provider, or None if SSO is disabled.
+pub fn provider() -> Option<&'static Provider> {
+    config::get().oidc.as_ref()
+}
+
+/// The parts of the provider's `/.well-known/openid-configuration` we use.
+#[derive(Deserialize, Debug, Clone)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: String,
+}
+
+#[derive(Deserialize, Clone)]
+struct TokenResponse {
+    access_token: String,
+    id_token: String,
+}
+
+/// The claims of the ID token that are checked.
+#[derive(Deserialize, Debug, Clone)]
+struct IdTokenClaims {
+    iss: String,
+    sub: String,
+    aud: Audience,
+    /// Seconds since the epoch.
+    exp: i64,
+}
+
+/// `aud` is either a single client id or a list of them.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Audience {
+    fn contains(&self, client_id: &str) -> bool {
+        match self {
+            Audience::One(aud) => aud == client_id,
+            Audience::Many(auds) => auds.iter().any(|aud| aud == client_id),
+        }
+    }
+}
+
+/// Claims returned by the provider's userinfo endpoint.
+#[derive(Deserialize, Debug, Clone)]
+pub struct UserInfo {
+    pub sub: String,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+}
+
+/// Where to send the user to log in, along with what has to be kept until the callback.
+#[derive(Clone)]
+pub struct AuthorizationRequest {
+    pub url: String,
+    pub state: String,
+    pub code_verifier: String,
+}
+
+/// Keeps the PKCE verifier out of logs.
+impl std::fmt::Debug for AuthorizationRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuthorizationRequest")
+            .field("url", &self.url)
+            .field("state", &self.state)
+            .field("code_verifier", &"[redacted]")
+            .finish()
+    }
+}
+
+/// Fetches the provider metadata once and caches it.
+async fn discovery(provider: &Provider) -> Result<&'static Discovery, Error> {
+    static DISCOVERY: OnceCell<Discovery> = OnceCell::const_new();
+    DISCOVERY
+        .get_or_try_init(|| async {
+            let url = format!("{}/.well-known/openid-configuration", provider.issuer);
+            let res = reqwest::get(url).await.map_err(Error::ExternalAPIError)?;
+            if !res.status().is_success() {
+                return Err(Error::OidcError(format!(
+                    "discovery returned {}",
+                    res.status()
+                )));
+            }
+            let discovery = res
+                .json::<Discovery>()
+                .await
+                .map_err(Error::ExternalAPIError)?;
+            if discovery.issuer.trim_end_matches('/') != provider.issuer {
+                return Err(Error::OidcError(format!(
+                    "discovery issuer {} doesn't match {}",
+                    discovery.issuer, provider.issuer
+                )));
+            }
+            Ok(discovery)
+        })
+        .await
+}
+
+fn random_string(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+/// Builds the authorization code request with a fresh state and PKCE verifier.
+pub async fn authorization_request(provider: &Provider) -> Result<AuthorizationRequest, Error> {
+    let discovery = discovery(provider).await?;
+    let state = random_string(32);
+    let code_verifier = random_string(64);
+    let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+
+    let mut url = Url::parse(&discovery.authorization_endpoint)
+        .map_err(|e| Error::OidcError(format!("bad authorization endpoint: {}", e)))?;
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &provider.client_id)
+        .append_pair("redirect_uri", &provider.redirect_url)
+        .append_pair("scope", "openid email")
+        .append_pair("state", &state)
+        .append_pair("code_challenge", &code_challenge)
+        .append_pair("code_challenge_method", "S256");
+
+    Ok(AuthorizationRequest {
+        url: url.to_string(),
+        state,
+        code_verifier,
+    })
+}
+
+/// Checks the ID token was issued by the provider for us and hasn't expired, and returns its
+/// claims.
+///
+/// The signature isn't checked: the token comes straight from the token endpoint, which OIDC
+/// Core 3.1.3.7 allows to rely on TLS for instead in the authorization code flow.
+fn id_token_claims(
+    provider: &Provider,
+    discovery: &Discovery,
+    id_token: &str,
+) -> Result<IdTokenClaims, Error> {
+    let claims = id_token
+        .split('.')
+        .nth(1)
+        .and_then(|claims| URL_SAFE_NO_PAD.decode(claims).ok())
+        .and_then(|claims| serde_json::from_slice::<IdTokenClaims>(&claims).ok())
+        .ok_or_else(|| Error::OidcError("malformed ID token".to_string()))?;
+
+    if claims.iss != discovery.issuer {
+        return Err(Error::OidcError(format!(
+            "ID token issued by {}",
+            claims.iss
+        )));
+    }
+    if !claims.aud.contains(&provider.client_id) {
+        return Err(Error::OidcError(
+            "ID token issued for another client".to_string(),
+        ));
+    }
+    if claims.exp <= chrono::Utc::now().timestamp() {
+        return Err(Error::OidcError("ID token expired".to_string()));
+    }
+    Ok(claims)
+}
+
+/// Exchanges an authorization code for tokens and returns the user they belong to.
+pub async fn exchange_code(
+    provider: &Provider,
+    code: &str,
+    code_verifier: &str,
+) -> Result<UserInfo, Error> {
+    let discovery = discovery(provider).await?;
+    let client = reqwest::Client::new();
+
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", &provider.redirect_url),
+        ("client_id", &provider.client_id),
+        ("code_verifier", code_verifier),
+    ];
+    if let Some(secret) = &provider.client_secret {
+        form.push(("client_secret", secret.expose()));
+    }
+    let res = client
+        .post(&discovery.token_endpoint)
+        .form(&form)
+        .send()
+        .await
+        .map_err(Error::ExternalAPIError)?;
+    if !res.status().is_success() {
+        return Err(Error::OidcError(format!(
+            "token endpoint returned {}",
+            res.status()
+        )));
+    }
+    let token = res
+        .json::<TokenResponse>()
+        .await
+        .map_err(Error::ExternalAPIError)?;
+    let claims = id_token_claims(provider, discovery, &token.id_token)?;
+
+    let res = client
+        .get(&discovery.userinfo_endpoint)
+        .bearer_auth(token.access_token)
+        .send()
+        .await
+        .map_err(Error::ExternalAPIError)?;
+    if !res.status().is_success() {
+        return Err(Error::OidcError(format!(
+            "userinfo endpoint returned {}",
+            res.status()
+        )));
+    }
+    let user = res
+        .json::<UserInfo>()
+        .await
+        .map_err(Error::ExternalAPIError)?;
+    // Userinfo must be about the user the ID token was issued for.
+    if user.sub != claims.sub {
+        return Err(Error::OidcError(
+            "userinfo and ID token are about different users".to_string(),
+        ));
+    }
+    Ok(user)
+}
@@ -0,0 +1,435 @@
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use crate::{metrics, routes};
+
+/// Routes outside of the versioned API, see `document` for the rest.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Q&A API",
+        description = "Questions and answers, with accounts, API keys and SSO."
+    ),
+    paths(
+        routes::graphql::graphql,
+        routes::health::healthz,
+        routes::health::readyz,
+        routes::docs::openapi_json,
+        metrics::metrics,
+    ),
+    modifiers(&SecurityAddon),
+    tags(
+        (name = "questions"),
+        (name = "answers"),
+        (name = "tags", description = "Tags of questions, their synonyms and merging them"),
+        (name = "accounts", description = "Registration, login, API keys, sessions, follows and notifications"),
+        (name = "events", description = "Changes streamed over Server-Sent Events or a WebSocket"),
+        (name = "graphql", description = "Questions, answers and accounts in one round trip"),
+        (name = "operations", description = "Health checks, metrics and this document"),
+    )
+)]
+struct ApiDoc;
+
+/// Routes under `/v1`.
+#[derive(OpenApi)]
+#[openapi(paths(
+    routes::question::get_questions,
+    routes::question::add_question,
+    routes::question::update_question,
+    routes::question::delete_question,
+    routes::answer::add_answer,
+    routes::authentication::register,
+    routes::authentication::login,
+    routes::oidc::oidc_login,
+    routes::oidc::oidc_callback,
+    routes::api_key::add_api_key,
+    routes::api_key::get_api_keys,
+    routes::api_key::delete_api_key,
+    routes::session::get_sessions,
+    routes::session::delete_session,
+))]
+struct V1Api;
+
+/// Routes under `/v2`.
+#[derive(OpenApi)]
+#[openapi(paths(
+    routes::question::get_questions,
+    routes::v2::get_question,
+    routes::v2::add_question,
+    routes::v2::update_question,
+    routes::v2::patch_question,
+    routes::revision::get_revisions,
+    routes::revision::rollback_question,
+    routes::question::delete_question,
+    routes::question::restore_question,
+    routes::v2::get_answer,
+    routes::v2::add_answer,
+    routes::answer::delete_answer,
+    routes::answer::restore_answer,
+    routes::tag::get_tags,
+    routes::tag::get_tag,
+    routes::tag::update_tag,
+    routes::tag::merge_tag,
+    routes::follow::get_feed,
+    routes::event::events,
+    routes::event::events_ws,
+    routes::v2::register,
+    routes::v2::get_account,
+    routes::authentication::login,
+    routes::oidc::oidc_login,
+    routes::oidc::oidc_callback,
+    routes::api_key::add_api_key,
+    routes::api_key::get_api_keys,
+    routes::api_key::delete_api_key,
+    routes::session::get_sessions,
+    routes::session::delete_session,
+    routes::follow::get_follows,
+    routes::follow::follow_tag,
+    routes::follow::unfollow_tag,
+    routes::follow::follow_question,
+    routes::follow::unfollow_question,
+    routes::notification::get_notifications,
+    routes::notification::get_unread_count,
+    routes::notification::mark_read,
+    routes::notification::mark_all_read,
+))]
+struct V2Api;
+
+/// The OpenAPI document, built from the `#[utoipa::path]` attributes of the handlers.
+///
+/// Every route in `main.rs` has to be listed in one of the docs above, the test below checks
+/// that. The deprecated root aliases of `/v1` are left out.
+pub fn document() -> utoipa::openapi::OpenApi {
+    ApiDoc::openapi()
+        .nest("/v1", versioned("v1", V1Api::openapi()))
+        .nest("/v2", versioned("v2", V2Api::openapi()))
+}
+
+/// Prefixes the operation ids in api with version, they have to be unique in the document.
+fn versioned(version: &str, mut api: utoipa::openapi::OpenApi) -> utoipa::openapi::OpenApi {
+    for item in api.paths.paths.values_mut() {
+        for operation in [
+            &mut item.get,
+            &mut item.post,
+            &mut item.put,
+            &mut item.patch,
+            &mut item.delete,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            operation.operation_id = operation
+                .operation_id
+                .take()
+                .map(|id| format!("{}_{}", version, id));
+        }
+    }
+    api
+}
+
+/// Adds the ways to authenticate referred to by the handlers.
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "token",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("PASETO")
+                    .description(Some(
+                        "Token from `/v2/login`, API keys are accepted here as well",
+                    ))
+                    .build(),
+            ),
+        );
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                "X-Api-Key",
+                "Key from `/v2/accounts/me/api_keys`, limited to its scopes",
+            ))),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use syn::visit::Visit;
+    use syn::{Expr, ExprCall, ExprMethodCall, Lit};
+
+    use super::*;
+    use crate::versioning;
+
+    /// Routes that aren't part of the API.
+    const UNDOCUMENTED: &[&str] = &["GET /docs"];
+
+    /// Returns the function called by call, e.g. `warp::path::param`.
+    fn called(call: &ExprCall) -> Option<String> {
+        match &*call.func {
+            Expr::Path(path) => Some(
+                path.path
+                    .segments
+                    .iter()
+                    .map(|s| s.ident.to_string())
+                    .collect::<Vec<_>>()
+                    .join("::"),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Returns the string literal passed to `warp::path`, if expr is such a call.
+    fn path_segment(expr: &Expr) -> Option<String> {
+        match expr {
+            Expr::Call(call) if called(call).as_deref() == Some("warp::path") => {
+                match call.args.first() {
+                    Some(Expr::Lit(lit)) => match &lit.lit {
+                        Lit::Str(s) => Some(s.value()),
+                        _ => None,
+                    },
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the variable expr refers to, looking through `.clone()`.
+    fn variable(expr: &Expr) -> Option<String> {
+        match expr {
+            Expr::Path(path) => path.path.get_ident().map(|i| i.to_string()),
+            Expr::MethodCall(call) if call.method == "clone" => variable(&call.receiver),
+            _ => None,
+        }
+    }
+
+    /// Turns a filter chain like `warp::get().and(warp::path("questions")).and(...)` into
+    /// `GET /questions/{}`, or None if expr isn't a route.
+    fn route(mut expr: &Expr) -> Option<String> {
+        let mut segments = vec![];
+        let method = loop {
+            match expr {
+                Expr::MethodCall(call) => {
+                    if let Some(arg) = call.args.first() {
+                        if let Some(segment) = path_segment(arg) {
+                            segments.push(segment);
+                        } else if let Expr::Call(arg) = arg {
+                            if called(arg).as_deref() == Some("warp::path::param") {
+                                segments.push("{}".to_string());
+                            }
+                        }
+                    }
+                    expr = &call.receiver;
+                }
+                Expr::Call(call) => match called(call)?.as_str() {
+                    "warp::get" => break "GET",
+                    "warp::post" => break "POST",
+                    "warp::put" => break "PUT",
+                    "warp::patch" => break "PATCH",
+                    "warp::delete" => break "DELETE",
+                    _ => return None,
+                },
+                _ => return None,
+            }
+        };
+        segments.reverse();
+        Some(format!("{} /{}", method, segments.join("/")))
+    }
+
+    /// Returns the variables combined with `.or()` in a chain like `a.clone().or(b).boxed()`.
+    fn group(mut expr: &Expr) -> Option<Vec<String>> {
+        let mut members = vec![];
+        loop {
+            match expr {
+                Expr::MethodCall(call) if call.method == "or" => {
+                    members.push(variable(call.args.first()?)?);
+                    expr = &call.receiver;
+                }
+                Expr::MethodCall(call)
+                    if ["clone", "map", "boxed"].contains(&&*call.method.to_string()) =>
+                {
+                    expr = &call.receiver;
+                }
+                _ => {
+                    members.push(variable(expr)?);
+                    return Some(members);
+                }
+            }
+        }
+    }
+
+    /// Routes bound with `let` in a file, and the groups of them mounted under a prefix like
+    /// `warp::path("v1").and(v1)`.
+    #[derive(Default)]
+    struct Routes {
+        routes: BTreeMap<String, String>,
+        groups: BTreeMap<String, Vec<String>>,
+        prefixes: Vec<(String, String)>,
+    }
+
+    impl<'ast> Visit<'ast> for Routes {
+        fn visit_local(&mut self, local: &'ast syn::Local) {
+            if let (syn::Pat::Ident(name), Some(init)) = (&local.pat, &local.init) {
+                let name = name.ident.to_string();
+                if let Some(route) = route(&init.expr) {
+                    self.routes.insert(name, route);
+                } else if let Some(members) = group(&init.expr) {
+                    // Groups can be made of smaller groups, split up to keep the filters small.
+                    let routes: Option<Vec<Vec<String>>> = members
+                        .iter()
+                        .map(|m| match self.routes.contains_key(m) {
+                            true => Some(vec![m.clone()]),
+                            false => self.groups.get(m).cloned(),
+                        })
+                        .collect();
+                    if let Some(routes) = routes {
+                        self.groups.insert(name, routes.concat());
+                    }
+                }
+            }
+            syn::visit::visit_local(self, local);
+        }
+
+        fn visit_expr_method_call(&mut self, call: &'ast ExprMethodCall) {
+            if call.method == "and" {
+                if let (Some(prefix), Some(group)) = (
+                    path_segment(&call.receiver),
+                    call.args.first().and_then(variable),
+                ) {
+                    self.prefixes.push((prefix, group));
+                }
+            }
+            syn::visit::visit_expr_method_call(self, call);
+        }
+    }
+
+    fn parse_main() -> Routes {
+        let file = syn::parse_file(include_str!("main.rs")).unwrap();
+        let mut routes = Routes::default();
+        routes.visit_file(&file);
+        routes
+    }
+
+    /// Returns the routes of a group with the prefix it's mounted under.
+    fn prefixed(routes: &Routes, prefix: &str, group: &str) -> Vec<String> {
+        routes.groups[group]
+            .iter()
+            .map(|member| {
+                let (method, path) = routes.routes[member].split_once(' ').unwrap();
+                format!("{} /{}{}", method, prefix, path)
+            })
+            .collect()
+    }
+
+    /// Routes in main.rs, with the ones in groups only under their prefixes.
+    fn served() -> BTreeSet<String> {
+        let routes = parse_main();
+        let grouped: BTreeSet<&String> = routes.groups.values().flatten().collect();
+        let mut served: BTreeSet<String> = routes
+            .routes
+            .iter()
+            .filter(|(name, _)| !grouped.contains(name))
+            .map(|(_, route)| route.clone())
+            .collect();
+        for (prefix, group) in &routes.prefixes {
+            if routes.groups.contains_key(group) {
+                served.extend(prefixed(&routes, prefix, group));
+            }
+        }
+        served
+            .into_iter()
+            .filter(|r| !UNDOCUMENTED.contains(&r.as_str()))
+            .collect()
+    }
+
+    fn documented() -> BTreeSet<String> {
+        let mut routes = BTreeSet::new();
+        for (path, item) in document().paths.paths {
+            // Parameter names don't matter, the routes in main.rs don't have any.
+            let path = path
+                .split('/')
+                .map(|s| if s.starts_with('{') { "{}" } else { s })
+                .collect::<Vec<_>>()
+                .join("/");
+            for (method, operation) in [
+                ("GET", &item.get),
+                ("POST", &item.post),
+                ("PUT", &item.put),
+                ("PATCH", &item.patch),
+                ("DELETE", &item.delete),
+            ] {
+                if operation.is_some() {
+                    routes.insert(format!("{} {}", method, path));
+                }
+            }
+        }
+        routes
+    }
+
+    #[test]
+    fn every_route_is_documented() {
+        let served = served();
+        assert!(!served.is_empty(), "no routes found in main.rs");
+        let documented = documented();
+
+        let undocumented: Vec<_> = served.difference(&documented).collect();
+        assert!(
+            undocumented.is_empty(),
+            "routes missing from the OpenAPI docs: {:?}",
+            undocumented
+        );
+        let stale: Vec<_> = documented.difference(&served).collect();
+        assert!(
+            stale.is_empty(),
+            "documented routes not served: {:?}",
+            stale
+        );
+    }
+
+    #[test]
+    fn root_aliases_are_the_v1_routes() {
+        let routes = parse_main();
+        let (_, v1) = routes
+            .prefixes
+            .iter()
+            .find(|(prefix, _)| prefix == "v1")
+            .expect("no v1 routes in main.rs");
+        let first_segments: BTreeSet<String> = routes.groups[v1]
+            .iter()
+            .map(|member| {
+                let (_, path) = routes.routes[member].split_once(' ').unwrap();
+                path.split('/').nth(1).unwrap().to_string()
+            })
+            .collect();
+        let aliases: BTreeSet<String> = versioning::ROOT_ALIASES
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(first_segments, aliases);
+    }
+
+    #[test]
+    fn operation_ids_are_unique() {
+        let mut seen = BTreeSet::new();
+        for item in document().paths.paths.values() {
+            for operation in [&item.get, &item.post, &item.put, &item.patch, &item.delete]
+                .into_iter()
+                .flatten()
+            {
+                let id = operation.operation_id.clone().unwrap_or_default();
+                assert!(seen.insert(id.clone()), "duplicate operation id {}", id);
+            }
+        }
+    }
+
+    #[test]
+    fn openapi_version_is_3_1() {
+        let json = serde_json::to_value(document()).unwrap();
+        assert_eq!(json["openapi"], "3.1.0");
+    }
+}
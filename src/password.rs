@@ -0,0 +1,252 @@
+use std::sync::OnceLock;
+
+use argon2::{Config, Variant};
+use handle_errors::Error;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+
+/// Argon2id cost parameters new hashes are created with.
+///
+/// Defaults to the OWASP recommended minimum for Argon2id.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HashParams {
+    #[serde(rename = "memory_kib")]
+    pub mem_cost: u32,
+    #[serde(rename = "iterations")]
+    pub time_cost: u32,
+    #[serde(rename = "parallelism")]
+    pub lanes: u32,
+}
+
+impl Default for HashParams {
+    fn default() -> Self {
+        HashParams {
+            mem_cost: 19 * 1024,
+            time_cost: 2,
+            lanes: 1,
+        }
+    }
+}
+
+impl HashParams {
+    fn config(&self) -> Config<'static> {
+        Config {
+            variant: Variant::Argon2id,
+            mem_cost: self.mem_cost,
+            time_cost: self.time_cost,
+            lanes: self.lanes,
+            ..Config::default()
+        }
+    }
+
+    /// Parses the variant and parameters out of a PHC encoded hash such as
+    /// `$argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>`.
+    fn from_encoded(hash: &str) -> Option<(Variant, HashParams)> {
+        let mut parts = hash.split('$').skip(1);
+        let variant = Variant::from_str(parts.next()?).ok()?;
+        let mut params = parts.next()?;
+        if params.starts_with("v=") {
+            params = parts.next()?;
+        }
+
+        let (mut mem_cost, mut time_cost, mut lanes) = (None, None, None);
+        for param in params.split(',') {
+            let (k, v) = param.split_once('=')?;
+            let v = v.parse::<u32>().ok()?;
+            match k {
+                "m" => mem_cost = Some(v),
+                "t" => time_cost = Some(v),
+                "p" => lanes = Some(v),
+                _ => {}
+            }
+        }
+        Some((
+            variant,
+            HashParams {
+                mem_cost: mem_cost?,
+                time_cost: time_cost?,
+                lanes: lanes?,
+            },
+        ))
+    }
+}
+
+/// Returns the hashing parameters of the current policy.
+pub fn params() -> &'static HashParams {
+    &config::get().auth.argon2
+}
+
+fn hash_blocking(pwd: &[u8]) -> Result<String, argon2::Error> {
+    let salt = rand::thread_rng().gen::<[u8; 32]>();
+    argon2::hash_encoded(pwd, &salt, &params().config())
+}
+
+/// Hash used to verify passwords for unknown emails, so they take as long as a wrong password.
+fn dummy_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    DUMMY_HASH.get_or_init(|| hash_blocking(b"not-a-real-password").unwrap())
+}
+
+/// Hashes pwd with the current policy on the blocking thread pool.
+pub async fn hash(pwd: String) -> Result<String, Error> {
+    tokio::task::spawn_blocking(move || hash_blocking(pwd.as_bytes()))
+        .await
+        .expect("password hashing task panicked")
+        .map_err(Error::ArgonLibraryError)
+}
+
+/// Verifies pwd against hash on the blocking thread pool.
+///
+/// If hash is None pwd is checked against a dummy hash so that callers looking up unknown
+/// accounts spend the same time as for a wrong password. It never verifies.
+pub async fn verify(hash: Option<String>, pwd: String) -> Result<bool, Error> {
+    tokio::task::spawn_blocking(move || match hash {
+        Some(hash) => argon2::verify_encoded(&hash, pwd.as_bytes()),
+        None => argon2::verify_encoded(dummy_hash(), pwd.as_bytes()).map(|_| false),
+    })
+    .await
+    .expect("password verification task panicked")
+    .map_err(Error::ArgonLibraryError)
+}
+
+/// Returns true if hash was created with a weaker variant or parameters than the current
+/// policy and should be replaced.
+pub fn needs_rehash(hash: &str) -> bool {
+    let current = params();
+    match HashParams::from_encoded(hash) {
+        Some((variant, p)) => {
+            variant != Variant::Argon2id
+                || p.mem_cost < current.mem_cost
+                || p.time_cost < current.time_cost
+                || p.lanes < current.lanes
+        }
+        None => true,
+    }
+}
+
+const MIN_LENGTH: usize = 10;
+/// Long passwords are only slow to hash, there's no point accepting more than this.
+const MAX_LENGTH: usize = 128;
+/// Passwords at least this long are accepted without requiring mixed character classes.
+const PASSPHRASE_LENGTH: usize = 16;
+const COMMON_PASSWORDS: &[&str] = &[
+    "password",
+    "password1",
+    "password123",
+    "123456789",
+    "1234567890",
+    "qwertyuiop",
+    "iloveyou",
+    "letmein",
+    "welcome",
+    "admin",
+];
+
+/// Checks password is strong enough to register an account for email.
+///
+/// Returns Error::WeakPassword describing the first rule broken.
+pub fn check_strength(email: &str, password: &str) -> Result<(), Error> {
+    let len = password.chars().count();
+    if len < MIN_LENGTH {
+        return Err(Error::WeakPassword(format!(
+            "password must be at least {} characters",
+            MIN_LENGTH
+        )));
+    }
+    if len > MAX_LENGTH {
+        return Err(Error::WeakPassword(format!(
+            "password must be at most {} characters",
+            MAX_LENGTH
+        )));
+    }
+
+    let lower = password.to_lowercase();
+    // Common passwords with digits or symbols tacked on, like "Password123!", are as weak.
+    let stem = lower.trim_end_matches(|c: char| !c.is_alphabetic());
+    if COMMON_PASSWORDS.iter().any(|p| lower == *p || stem == *p) {
+        return Err(Error::WeakPassword("password is too common".to_string()));
+    }
+    let user = email.split('@').next().unwrap_or_default().to_lowercase();
+    if user.len() >= 3 && lower.contains(&user) {
+        return Err(Error::WeakPassword(
+            "password must not contain the email".to_string(),
+        ));
+    }
+
+    let classes = [
+        password.chars().any(|c| c.is_lowercase()),
+        password.chars().any(|c| c.is_uppercase()),
+        password.chars().any(|c| c.is_ascii_digit()),
+        password.chars().any(|c| !c.is_alphanumeric()),
+    ]
+    .iter()
+    .filter(|c| **c)
+    .count();
+    if len < PASSPHRASE_LENGTH && classes < 3 {
+        return Err(Error::WeakPassword(format!(
+            "password must mix lower case, upper case, digits or symbols, or be at least {} characters",
+            PASSPHRASE_LENGTH
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns why password is too weak for email, or None if it's accepted.
+    fn weakness(email: &str, password: &str) -> Option<String> {
+        match check_strength(email, password) {
+            Ok(()) => None,
+            Err(Error::WeakPassword(reason)) => Some(reason),
+            Err(e) => panic!("unexpected error {}", e),
+        }
+    }
+
+    #[test]
+    fn rejects_common_passwords() {
+        assert_eq!(
+            weakness("ann@example.com", "1234567890").as_deref(),
+            Some("password is too common")
+        );
+        assert_eq!(
+            weakness("ann@example.com", "QwertyUiop").as_deref(),
+            Some("password is too common")
+        );
+    }
+
+    #[test]
+    fn rejects_common_passwords_with_a_suffix() {
+        for password in [
+            "Password123!",
+            "Letmein2024!!",
+            "Welcome!2024",
+            "ADMIN#123456",
+        ] {
+            assert_eq!(
+                weakness("ann@example.com", password).as_deref(),
+                Some("password is too common"),
+                "{}",
+                password
+            );
+        }
+    }
+
+    #[test]
+    fn accepts_passphrases_starting_with_a_common_password() {
+        assert_eq!(weakness("ann@example.com", "password manager horse"), None);
+        assert_eq!(weakness("ann@example.com", "Letmein-Quietly-7"), None);
+    }
+
+    #[test]
+    fn rejects_the_email_user() {
+        assert_eq!(
+            weakness("ann.lee@example.com", "Xx-ann.lee-99").as_deref(),
+            Some("password must not contain the email")
+        );
+    }
+}
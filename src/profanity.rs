@@ -1,6 +1,17 @@
-use reqwest_middleware::ClientBuilder;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use http::Extensions;
+use opentelemetry::{global, propagation::Injector};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest_middleware::{ClientBuilder, Middleware, Next};
 use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
 use serde::{Deserialize, Serialize};
+use tracing::instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use utoipa::ToSchema;
+
+use crate::{config, metrics};
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct APIResponse {
@@ -25,20 +36,144 @@ struct BadWordsResponse {
     censored_content: String,
 }
 
+/// Number of times a request has been sent, kept in the request extensions across retries.
+#[derive(Clone)]
+struct Attempts(u32);
+
+/// Counts requests the retry middleware sends after the first one.
+struct CountRetries;
+
+#[async_trait::async_trait]
+impl Middleware for CountRetries {
+    async fn handle(
+        &self,
+        req: reqwest::Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<reqwest::Response> {
+        let attempts = extensions.get::<Attempts>().map_or(0, |a| a.0);
+        if attempts > 0 {
+            metrics::get().profanity_retries.inc();
+        }
+        extensions.insert(Attempts(attempts + 1));
+        next.run(req, extensions).await
+    }
+}
+
+/// Consecutive failed calls after which the circuit opens.
+const CIRCUIT_FAILURE_THRESHOLD: u32 = 5;
+/// How long an open circuit fails calls right away before letting calls through again.
+const CIRCUIT_COOLDOWN: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Default)]
+struct Circuit {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+/// State of the circuit breaker in front of the content filter API.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    /// Calls go through.
+    Closed,
+    /// The API failed repeatedly, calls fail right away.
+    Open,
+    /// The cooldown passed, calls go through to probe whether the API recovered.
+    HalfOpen,
+}
+
+fn circuit() -> &'static Mutex<Circuit> {
+    static CIRCUIT: OnceLock<Mutex<Circuit>> = OnceLock::new();
+    CIRCUIT.get_or_init(|| Mutex::new(Circuit::default()))
+}
+
+pub fn circuit_state() -> CircuitState {
+    match circuit().lock().unwrap().open_until {
+        Some(until) if until > Instant::now() => CircuitState::Open,
+        Some(_) => CircuitState::HalfOpen,
+        None => CircuitState::Closed,
+    }
+}
+
+/// Updates the circuit with the outcome of a call.
+///
+/// Client errors mean the API is up but didn't like our request, so they don't count.
+fn record_outcome<T>(res: &Result<T, handle_errors::Error>) {
+    let mut circuit = circuit().lock().unwrap();
+    match res {
+        Ok(_) | Err(handle_errors::Error::ClientError(_)) => *circuit = Circuit::default(),
+        Err(_) => {
+            circuit.failures += 1;
+            if circuit.failures >= CIRCUIT_FAILURE_THRESHOLD || circuit.open_until.is_some() {
+                circuit.open_until = Some(Instant::now() + CIRCUIT_COOLDOWN);
+            }
+        }
+    }
+}
+
+/// Lets the OpenTelemetry propagator write trace context into outgoing request headers.
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl<'a> Injector for HeaderInjector<'a> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+/// Censors bad words in content, recording latency and errors of the API call.
+///
+/// Fails right away with ContentFilterUnavailable while the circuit is open.
+#[instrument(skip_all)]
 pub async fn check_profanity(content: String) -> Result<String, handle_errors::Error> {
+    if circuit_state() == CircuitState::Open {
+        metrics::get().profanity_errors.inc();
+        return Err(handle_errors::Error::ContentFilterUnavailable);
+    }
+
+    let timer = metrics::get().profanity_duration.start_timer();
+    let res = call_bad_words_api(content).await;
+    timer.observe_duration();
+    record_outcome(&res);
+    if res.is_err() {
+        metrics::get().profanity_errors.inc();
+    }
+    res
+}
+
+async fn call_bad_words_api(content: String) -> Result<String, handle_errors::Error> {
     let retry_policy = ExponentialBackoff::builder().build_with_max_retries(3);
-    let api_key = std::env::var("BAD_WORDS_API_KEY").unwrap();
+    let content_filter = &config::get().content_filter;
 
     let client = ClientBuilder::new(reqwest::Client::new())
         .with(RetryTransientMiddleware::new_with_policy(retry_policy))
+        .with(CountRetries)
         .build();
+
+    let mut headers = HeaderMap::new();
+    global::get_text_map_propagator(|p| {
+        p.inject_context(
+            &tracing::Span::current().context(),
+            &mut HeaderInjector(&mut headers),
+        )
+    });
+
     let res = client
-        .post("https://api.apilayer.com/bad_words?censor_character=*")
-        .header("apikey", api_key)
+        .post(format!(
+            "{}/bad_words?censor_character=*",
+            content_filter.url
+        ))
+        .headers(headers)
+        .header("apikey", content_filter.api_key.expose())
         .body(content)
         .send()
         .await
-        .map_err(|e| handle_errors::Error::MiddlewareReqwesAPIError(e))?;
+        .map_err(handle_errors::Error::MiddlewareReqwesAPIError)?;
 
     if !res.status().is_success() {
         if res.status().is_client_error() {
@@ -0,0 +1,29 @@
+use std::time::Duration;
+
+use crate::config::DeletionConfig;
+use crate::store::Store;
+
+/// How often deleted questions and answers past their retention are looked for.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Starts deleting questions and answers for good once they've been deleted for longer than the
+/// configured retention.
+pub fn spawn(config: &DeletionConfig, store: Store) {
+    let retention = config.retention();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Ok((questions, answers)) = store.purge_deleted(retention).await {
+                if questions > 0 || answers > 0 {
+                    tracing::event!(
+                        tracing::Level::INFO,
+                        questions,
+                        answers,
+                        "purged deleted questions and answers"
+                    );
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,335 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use handle_errors::{Error, RateLimit};
+use serde::{Deserialize, Serialize};
+use warp::{Filter, Rejection};
+
+use crate::config::{RateLimitBackend, RateLimitConfig};
+use crate::metrics;
+use crate::server;
+use crate::store::Store;
+use crate::types::account::{AccountId, Session};
+
+/// Size of a token bucket and how fast it refills.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Limit {
+    /// Requests that can be made in a burst.
+    pub burst: u32,
+    /// Requests allowed per minute on average.
+    pub per_minute: u32,
+}
+
+impl Limit {
+    fn per_sec(&self) -> f64 {
+        self.per_minute as f64 / 60.0
+    }
+
+    /// Seconds an empty bucket takes to fill up.
+    fn fill_secs(&self) -> f64 {
+        self.burst as f64 / self.per_sec()
+    }
+}
+
+/// Kinds of requests, each with their own limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Class {
+    Read,
+    Write,
+    Registration,
+    Login,
+}
+
+impl Class {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Class::Read => "read",
+            Class::Write => "write",
+            Class::Registration => "registration",
+            Class::Login => "login",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// When the bucket will have refilled completely.
+    full_at: Instant,
+}
+
+/// Buckets are only kept in memory beyond this many until they'd be full again.
+const MAX_IDLE_BUCKETS: usize = 10_000;
+/// How often full buckets are looked for beyond `MAX_IDLE_BUCKETS`, as that goes through all.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+/// How often idle buckets are deleted from the DB.
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Default)]
+struct Buckets {
+    buckets: HashMap<String, Bucket>,
+    next_sweep: Option<Instant>,
+}
+
+/// What's left of the limit of a request, sent back in the `X-RateLimit-*` headers.
+#[derive(Debug, Clone, Copy)]
+pub struct Quota {
+    pub limit: u32,
+    pub remaining: u32,
+    /// Seconds until the full burst is available again.
+    pub reset: u64,
+}
+
+tokio::task_local! {
+    /// Quota of the request being handled, set by the last limit checked.
+    static QUOTA: Cell<Option<Quota>>;
+}
+
+/// Runs the handling of a request, returning its output and the quota of the limit checked.
+pub async fn with_quota<F: Future>(handle: F) -> (F::Output, Option<Quota>) {
+    QUOTA
+        .scope(Cell::new(None), async move {
+            let output = handle.await;
+            (output, QUOTA.with(Cell::get))
+        })
+        .await
+}
+
+#[derive(Debug, Clone)]
+enum Backend {
+    Disabled,
+    Memory(Arc<Mutex<Buckets>>),
+    /// Shared by all replicas using the DB.
+    Postgres(Store),
+}
+
+/// Token bucket rate limits, per account for authenticated routes and per client IP otherwise.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    backend: Backend,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig, store: Store) -> Self {
+        let backend = match (config.enabled, config.backend) {
+            (false, _) => Backend::Disabled,
+            (true, RateLimitBackend::Memory) => Backend::Memory(Arc::default()),
+            (true, RateLimitBackend::Postgres) => {
+                // Deleting a bucket is the same as refilling it, so once all could have filled
+                // up they don't need to be kept.
+                let idle_secs = [config.read, config.write, config.registration, config.login]
+                    .iter()
+                    .map(Limit::fill_secs)
+                    .fold(0.0, f64::max)
+                    .ceil() as i64;
+                let cleanup_store = store.clone();
+                tokio::spawn(async move {
+                    let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+                    loop {
+                        interval.tick().await;
+                        cleanup_store.delete_idle_rate_limits(idle_secs).await;
+                    }
+                });
+                Backend::Postgres(store)
+            }
+        };
+        RateLimiter {
+            config: config.clone(),
+            backend,
+        }
+    }
+
+    fn limit(&self, class: Class) -> Limit {
+        match class {
+            Class::Read => self.config.read,
+            Class::Write => self.config.write,
+            Class::Registration => self.config.registration,
+            Class::Login => self.config.login,
+        }
+    }
+
+    /// Takes a token from key's bucket in memory at now, returning the tokens left and whether
+    /// one could be taken.
+    fn take_from_memory(
+        buckets: &Mutex<Buckets>,
+        key: String,
+        limit: Limit,
+        now: Instant,
+    ) -> (f64, bool) {
+        let refill = |bucket: &Bucket| {
+            let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+            (bucket.tokens + elapsed * limit.per_sec()).min(limit.burst as f64)
+        };
+
+        let mut buckets = buckets.lock().unwrap();
+        if buckets.buckets.len() > MAX_IDLE_BUCKETS
+            && buckets.next_sweep.is_none_or(|next| next <= now)
+        {
+            buckets.buckets.retain(|_, bucket| bucket.full_at > now);
+            buckets.next_sweep = Some(now + SWEEP_INTERVAL);
+        }
+        let bucket = buckets.buckets.entry(key).or_insert(Bucket {
+            tokens: limit.burst as f64,
+            updated: now,
+            full_at: now,
+        });
+        let tokens = refill(bucket);
+        let allowed = tokens >= 1.0;
+        bucket.tokens = if allowed { tokens - 1.0 } else { tokens };
+        bucket.updated = now;
+        bucket.full_at =
+            now + Duration::from_secs_f64((limit.burst as f64 - bucket.tokens) / limit.per_sec());
+        (bucket.tokens, allowed)
+    }
+
+    /// Takes a token from the bucket of class and key.
+    ///
+    /// Fails with Error::RateLimited when the bucket is empty. If the DB can't be reached the
+    /// request is let through.
+    async fn check(&self, class: Class, key: String) -> Result<(), Error> {
+        let limit = self.limit(class);
+        let key = format!("{}:{}", class.as_str(), key);
+        let (tokens, allowed) = match &self.backend {
+            Backend::Disabled => return Ok(()),
+            Backend::Memory(buckets) => Self::take_from_memory(buckets, key, limit, Instant::now()),
+            Backend::Postgres(store) => {
+                match store
+                    .take_rate_limit_token(&key, limit.burst as f64, limit.per_sec())
+                    .await
+                {
+                    Ok(bucket) => bucket,
+                    Err(_) => return Ok(()),
+                }
+            }
+        };
+        let reset = ((limit.burst as f64 - tokens) / limit.per_sec()).ceil() as u64;
+        // Outside of requests, e.g. in tests, there's no quota to set.
+        let _ = QUOTA.try_with(|quota| {
+            quota.set(Some(Quota {
+                limit: limit.burst,
+                remaining: tokens.floor() as u32,
+                reset,
+            }))
+        });
+        if allowed {
+            return Ok(());
+        }
+
+        metrics::get()
+            .rate_limited
+            .with_label_values(&[class.as_str()])
+            .inc();
+        Err(Error::RateLimited(RateLimit {
+            limit: limit.burst,
+            retry_after: ((1.0 - tokens) / limit.per_sec()).ceil().max(1.0) as u64,
+            reset,
+        }))
+    }
+
+    /// Takes a token from the bucket of class for account_id, for checks outside of filters.
+    pub async fn check_account(&self, class: Class, account_id: &AccountId) -> Result<(), Error> {
+        self.check(class, format!("account:{}", account_id.0)).await
+    }
+
+    /// Limits requests by client IP.
+    ///
+    /// Requests over a Unix socket have no IP and aren't limited, the proxy in front has to.
+    pub fn by_ip(&self, class: Class) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+        let limiter = self.clone();
+        server::remote()
+            .and_then(move |remote: Option<SocketAddr>| {
+                let limiter = limiter.clone();
+                async move {
+                    match remote {
+                        Some(remote) => limiter
+                            .check(class, format!("ip:{}", remote.ip()))
+                            .await
+                            .map_err(warp::reject::custom),
+                        None => Ok(()),
+                    }
+                }
+            })
+            .untuple_one()
+    }
+
+    /// Limits requests by the account authenticated by auth, passing its session on.
+    pub fn by_account<F>(
+        &self,
+        class: Class,
+        auth: F,
+    ) -> impl Filter<Extract = (Session,), Error = Rejection> + Clone
+    where
+        F: Filter<Extract = (Session,), Error = Rejection> + Clone,
+    {
+        let limiter = self.clone();
+        auth.and_then(move |session: Session| {
+            let limiter = limiter.clone();
+            async move {
+                limiter
+                    .check_account(class, &session.account_id)
+                    .await
+                    .map_err(warp::reject::custom)?;
+                Ok::<_, Rejection>(session)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMIT: Limit = Limit {
+        burst: 3,
+        per_minute: 60,
+    };
+
+    /// Takes count tokens of key at now, returning whether each could be taken.
+    fn take(buckets: &Mutex<Buckets>, key: &str, count: usize, now: Instant) -> Vec<bool> {
+        (0..count)
+            .map(|_| RateLimiter::take_from_memory(buckets, key.to_string(), LIMIT, now).1)
+            .collect()
+    }
+
+    #[test]
+    fn burst_runs_out() {
+        let buckets = Mutex::default();
+        let now = Instant::now();
+        assert_eq!(take(&buckets, "a", 4, now), [true, true, true, false]);
+        // Other keys have their own bucket.
+        assert_eq!(take(&buckets, "b", 1, now), [true]);
+    }
+
+    #[test]
+    fn refills_over_time() {
+        let buckets = Mutex::default();
+        let now = Instant::now();
+        take(&buckets, "a", 3, now);
+
+        let later = now + Duration::from_millis(1500);
+        let (tokens, allowed) =
+            RateLimiter::take_from_memory(&buckets, "a".to_string(), LIMIT, later);
+        assert!(allowed);
+        assert!((tokens - 0.5).abs() < 1e-9, "{} tokens left", tokens);
+        assert_eq!(take(&buckets, "a", 1, later), [false]);
+    }
+
+    #[test]
+    fn refills_up_to_the_burst() {
+        let buckets = Mutex::default();
+        let now = Instant::now();
+        take(&buckets, "a", 3, now);
+
+        let later = now + Duration::from_secs(3600);
+        let (tokens, _) = RateLimiter::take_from_memory(&buckets, "a".to_string(), LIMIT, later);
+        assert_eq!(tokens, LIMIT.burst as f64 - 1.0);
+        assert_eq!(take(&buckets, "a", 3, later), [true, true, false]);
+    }
+}
@@ -1,28 +1,138 @@
+use crate::config;
+use crate::etag;
 use crate::profanity::check_profanity;
+use crate::routes::question::restorable_by;
 use crate::store::Store;
 use crate::types::account::Session;
-use crate::types::answer::NewAnswer;
+use crate::types::answer::{Answer, NewAnswer};
+use crate::types::api_key::Scope;
+use crate::types::notification::mentions;
 
+use handle_errors::ErrorBody;
 use warp::http::StatusCode;
 
 /// Handler for creating answer.
+#[utoipa::path(
+    post,
+    path = "/answers",
+    tag = "answers",
+    request_body = NewAnswer,
+    security(("token" = []), ("api_key" = ["post_answers"])),
+    responses(
+        (status = 200, description = "Answer added, with bad words censored", body = String),
+        (status = 401, description = "Not logged in, or the API key lacks the scope", body = ErrorBody),
+        (status = 404, description = "No such question", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+        (status = 503, description = "Content filter unavailable", body = ErrorBody),
+    )
+)]
 pub async fn add_answer(
     session: Session,
     store: Store,
     new_answer: NewAnswer,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let content = match check_profanity(new_answer.content).await {
-        Ok(res) => res,
-        Err(e) => return Err(warp::reject::custom(e)),
-    };
+    create_answer(&session, &store, new_answer).await?;
+    Ok(warp::reply::with_status("Answer added", StatusCode::OK))
+}
+
+/// Censors and stores an answer written by session, shared by the versions of `add_answer`.
+///
+/// Notifies the asker and the accounts mentioned in the answer.
+pub async fn create_answer(
+    session: &Session,
+    store: &Store,
+    new_answer: NewAnswer,
+) -> Result<Answer, handle_errors::Error> {
+    if !session.allows(Scope::PostAnswers) {
+        return Err(handle_errors::Error::Unauthorized);
+    }
 
     let new_answer = NewAnswer {
-        content,
+        content: check_profanity(new_answer.content).await?,
         question_id: new_answer.question_id,
     };
 
-    match store.add_answer(new_answer, session.account_id).await {
-        Ok(_) => Ok(warp::reply::with_status("Answer added", StatusCode::OK)),
-        Err(e) => Err(warp::reject::custom(e)),
+    let answer = store
+        .add_answer(new_answer, session.account_id.clone())
+        .await?;
+    // Not getting notified doesn't undo the answer, the store logs why.
+    store
+        .add_notifications(
+            answer.question_id.0,
+            Some(answer.id.0),
+            &session.account_id,
+            mentions(&answer.content),
+        )
+        .await;
+    Ok(answer)
+}
+
+/// Delete handler for Answer, only its author may delete it.
+///
+/// The answer is hidden, and can be restored within the configured restore window. It's purged for
+/// good after the configured retention.
+#[utoipa::path(
+    delete,
+    path = "/answers/{id}",
+    tag = "answers",
+    params(("id" = i32, Path, description = "Answer id")),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "Answer deleted", body = String),
+        (status = 401, description = "Not logged in, or not the author", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn delete_answer(
+    id: i32,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if !session.is_login() || !store.is_answer_owner(id, &session.account_id).await? {
+        return Err(warp::reject::custom(handle_errors::Error::Unauthorized));
+    }
+
+    match store.delete_answer(id, &session.account_id).await {
+        None => Ok(warp::reply::with_status(
+            format!("Answer {} deleted", id),
+            StatusCode::OK,
+        )),
+        Some(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Restore handler for a deleted Answer.
+///
+/// Its author or a moderator may restore it within the configured window.
+#[utoipa::path(
+    post,
+    path = "/answers/{id}/restore",
+    tag = "answers",
+    params(("id" = i32, Path, description = "Answer id")),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The restored answer", body = Answer,
+            headers(("ETag" = String, description = "Version of the answer"))),
+        (status = 401, description = "Not logged in with a password", body = ErrorBody),
+        (status = 404, description = "No answer of yours deleted within the window", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn restore_answer(
+    id: i32,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let owner = restorable_by(&session, &store).await?;
+    let window = config::get().deletion.restore_window();
+    match store.restore_answer(id, owner.as_ref(), window).await? {
+        Some(answer) => Ok(warp::reply::with_header(
+            warp::reply::json(&answer),
+            "ETag",
+            etag::of_version(answer.version),
+        )),
+        None => Err(warp::reject::custom(handle_errors::Error::NotFound(
+            format!("deleted answer {}", id),
+        ))),
     }
 }
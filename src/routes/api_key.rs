@@ -0,0 +1,109 @@
+use crate::routes::authentication::{generate_api_key, hash_api_key, API_KEY_DISPLAY_LEN};
+use crate::store::Store;
+use crate::types::account::Session;
+use crate::types::api_key::{ApiKey, CreatedApiKey, NewApiKey};
+
+use handle_errors::{Error, ErrorBody};
+use warp::http::StatusCode;
+
+/// Handler for creating an API key for the logged in account.
+///
+/// The key is only ever returned in this response, the store keeps its hash. Keys can't be
+/// used to create further keys, this requires a session from `login`.
+#[utoipa::path(
+    post,
+    path = "/accounts/me/api_keys",
+    tag = "accounts",
+    request_body = NewApiKey,
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The new key, only returned this once", body = CreatedApiKey),
+        (status = 401, description = "Not logged in with a password", body = ErrorBody),
+        (status = 422, description = "No scopes given", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn add_api_key(
+    session: Session,
+    store: Store,
+    new_key: NewApiKey,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if !session.is_login() {
+        return Err(warp::reject::custom(Error::Unauthorized));
+    }
+    if new_key.scopes.is_empty() {
+        return Err(warp::reject::custom(Error::MissingParameters));
+    }
+
+    let key = generate_api_key();
+    let prefix = key[..API_KEY_DISPLAY_LEN].to_string();
+    match store
+        .add_api_key(new_key, prefix, hash_api_key(&key), &session.account_id)
+        .await
+    {
+        Ok(api_key) => Ok(warp::reply::json(&CreatedApiKey { api_key, key })),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Lists the API keys of the logged in account.
+#[utoipa::path(
+    get,
+    path = "/accounts/me/api_keys",
+    tag = "accounts",
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The account's API keys", body = [ApiKey]),
+        (status = 401, description = "Not logged in with a password", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn get_api_keys(
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if !session.is_login() {
+        return Err(warp::reject::custom(Error::Unauthorized));
+    }
+
+    match store.get_api_keys(&session.account_id).await {
+        Ok(keys) => Ok(warp::reply::json(&keys)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Revokes one of the logged in account's API keys.
+#[utoipa::path(
+    delete,
+    path = "/accounts/me/api_keys/{id}",
+    tag = "accounts",
+    params(("id" = i32, Path, description = "API key id")),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "API key deleted", body = String),
+        (status = 401, description = "Not logged in with a password", body = ErrorBody),
+        (status = 404, description = "No such API key", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn delete_api_key(
+    id: i32,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if !session.is_login() {
+        return Err(warp::reject::custom(Error::Unauthorized));
+    }
+
+    match store.delete_api_key(id, &session.account_id).await {
+        Ok(true) => Ok(warp::reply::with_status(
+            format!("API key {} deleted", id),
+            StatusCode::OK,
+        )),
+        Ok(false) => Err(warp::reject::custom(Error::NotFound(format!(
+            "API key {}",
+            id
+        )))),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
@@ -1,96 +1,324 @@
-use std::{env, future};
+use std::net::SocketAddr;
 
+use crate::config;
+use crate::metrics;
+use crate::password;
 use crate::store::Store;
-use crate::types::account::{Account, AccountId, Session};
+use crate::types::account::{Account, AccountId, AccountInfo, Session, SessionId};
 
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 
-use argon2::Config;
-use handle_errors::Error;
-use rand::Rng;
+use handle_errors::{Error, ErrorBody};
+use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
 use tracing::{instrument, Level};
 use warp::http::StatusCode;
 use warp::Filter;
 
 /// Registers a new account.
 ///
+/// The password has to pass the strength policy in `password::check_strength`.
+#[utoipa::path(
+    post,
+    path = "/registration",
+    tag = "accounts",
+    request_body = Account,
+    responses(
+        (status = 200, description = "Account added", body = String),
+        (status = 422, description = "Password too weak, or email taken", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
 pub async fn register(store: Store, account: Account) -> Result<impl warp::Reply, warp::Rejection> {
-    let hashed_password = hash_password(account.password.as_bytes());
+    create_account(&store, account).await?;
+    Ok(warp::reply::with_status("Account added", StatusCode::OK))
+}
+
+/// Checks the password and stores the account with it hashed, shared by the versions of
+/// `register`.
+pub async fn create_account(store: &Store, account: Account) -> Result<AccountInfo, Error> {
+    password::check_strength(&account.email, &account.password)?;
+    let hashed_password = password::hash(account.password).await?;
 
     let account = Account {
         id: account.id,
         email: account.email,
         password: hashed_password,
     };
+    let email = account.email.clone();
+    let id = store.add_account(account).await?;
+    Ok(AccountInfo { id, email })
+}
 
-    match store.add_account(account).await {
-        None => Ok(warp::reply::with_status("Account added", StatusCode::OK)),
-        Some(e) => Err(warp::reject::custom(e)),
+/// Failed logins allowed for a single email before it is locked out.
+const MAX_FAILURES_PER_EMAIL: i32 = 5;
+/// Failed logins allowed from a single IP before it is locked out. Higher than the per email
+/// limit as many users can share an IP.
+const MAX_FAILURES_PER_IP: i32 = 20;
+/// Failures older than this are forgotten.
+const FAILURE_WINDOW_SECS: i64 = 15 * 60;
+/// First lockout duration, doubled for every further failure.
+const LOCKOUT_BASE_SECS: i64 = 30;
+const LOCKOUT_MAX_SECS: i64 = 60 * 60;
+
+/// A key failed logins are tracked under, e.g. `email:foo@bar.com` or `ip:127.0.0.1`.
+struct ThrottleKey {
+    key: String,
+    max_failures: i32,
+}
+
+/// Returns the keys a login attempt counts against, the email's first.
+fn throttle_keys(email: &str, remote: Option<SocketAddr>) -> Vec<ThrottleKey> {
+    let mut keys = vec![ThrottleKey {
+        key: format!("email:{}", email.to_lowercase()),
+        max_failures: MAX_FAILURES_PER_EMAIL,
+    }];
+    if let Some(addr) = remote {
+        keys.push(ThrottleKey {
+            key: format!("ip:{}", addr.ip()),
+            max_failures: MAX_FAILURES_PER_IP,
+        });
     }
+    keys
+}
+
+/// Lockout duration once a key reached failures, backing off exponentially.
+fn lockout_secs(failures: i32, max_failures: i32) -> i64 {
+    let exp = (failures - max_failures).clamp(0, 16) as u32;
+    (LOCKOUT_BASE_SECS << exp).min(LOCKOUT_MAX_SECS)
+}
+
+async fn record_login_failure(store: &Store, keys: &[ThrottleKey]) -> Result<(), Error> {
+    for k in keys {
+        let failures = store
+            .record_login_failure(&k.key, FAILURE_WINDOW_SECS)
+            .await?;
+        if failures >= k.max_failures {
+            let secs = lockout_secs(failures, k.max_failures);
+            tracing::event!(target: "book", Level::WARN, key = %k.key, failures, secs, "login locked out");
+            if let Some(e) = store.lock_login(&k.key, secs).await {
+                return Err(e);
+            }
+        }
+    }
+    Ok(())
 }
 
 /// Logs user in.
 /// User stays logged in for X
-pub async fn login(store: Store, login: Account) -> Result<impl warp::Reply, warp::Rejection> {
-    match store.get_account(login.email).await {
-        Ok(account) => match verify_password(&account.password, login.password.as_bytes()) {
-            Ok(verified) => {
-                if verified {
-                    Ok(warp::reply::json(&issue_token(
-                        account.id.expect("id not found"),
-                    )))
-                } else {
-                    Err(warp::reject::custom(Error::WrongPassword))
-                }
+///
+/// Failed logins are tracked per email and per IP, once either has too many failures it is
+/// locked out for an exponentially growing period and 429 is returned.
+///
+/// Passwords hashed with weaker parameters than the current policy are rehashed on success.
+/// Each login is recorded as a session with the client's user agent and IP.
+#[utoipa::path(
+    post,
+    path = "/login",
+    tag = "accounts",
+    request_body = Account,
+    responses(
+        (status = 200, description = "Token to send as `Authorization: Bearer`", body = String),
+        (status = 401, description = "Wrong email or password", body = ErrorBody),
+        (status = 429, description = "Locked out after failed logins, or rate limited", body = ErrorBody),
+    )
+)]
+pub async fn login(
+    store: Store,
+    remote: Option<SocketAddr>,
+    user_agent: Option<String>,
+    login: Account,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let keys = throttle_keys(&login.email, remote);
+    let key_names: Vec<String> = keys.iter().map(|k| k.key.clone()).collect();
+    if let Some(retry_after) = store.login_lockout_remaining(&key_names).await? {
+        metrics::get().logins.with_label_values(&["locked"]).inc();
+        return Err(warp::reject::custom(Error::TooManyLoginAttempts(
+            retry_after.max(1) as u64,
+        )));
+    }
+
+    let account = store.get_account(login.email).await?;
+    let hash = account.as_ref().map(|a| a.password.clone());
+    let verified = password::verify(hash, login.password.clone()).await?;
+
+    match account {
+        Some(account) if verified => {
+            // Only the email is cleared, or an attacker could reset the IP's failures by logging
+            // into their own account in between guesses. The IP's expire after the window.
+            if let Some(e) = store.clear_login_failures(&keys[0].key).await {
+                return Err(warp::reject::custom(e));
             }
-            Err(e) => Err(warp::reject::custom(Error::ArgonLibraryError(e))),
-        },
-        Err(e) => Err(warp::reject::custom(e)),
+            let account_id = account.id.expect("id not found");
+            if password::needs_rehash(&account.password) {
+                rehash_password(&store, &account_id, login.password).await;
+            }
+            let token = start_session(&store, account_id, user_agent, remote).await?;
+            metrics::get().logins.with_label_values(&["success"]).inc();
+            Ok(warp::reply::json(&token))
+        }
+        _ => {
+            metrics::get().logins.with_label_values(&["failure"]).inc();
+            record_login_failure(&store, &keys).await?;
+            Err(warp::reject::custom(Error::WrongPassword))
+        }
     }
 }
 
-fn hash_password(pwd: &[u8]) -> String {
-    let salt = rand::thread_rng().gen::<[u8; 32]>();
-    let config = Config::default();
-
-    argon2::hash_encoded(pwd, &salt, &config).unwrap()
+/// Replaces the stored hash of account_id with one using the current policy.
+///
+/// Failures are only logged, the user already proved their password.
+async fn rehash_password(store: &Store, account_id: &AccountId, pwd: String) {
+    let res = match password::hash(pwd).await {
+        Ok(hash) => store.update_account_password(account_id, hash).await,
+        Err(e) => Some(e),
+    };
+    if let Some(e) = res {
+        tracing::event!(target: "book", Level::ERROR, "failed to rehash password: {}", e);
+    }
 }
 
-fn verify_password(hash: &str, pwd: &[u8]) -> Result<bool, argon2::Error> {
-    argon2::verify_encoded(hash, pwd)
+/// Records a new session for account_id and issues a token for it.
+pub async fn start_session(
+    store: &Store,
+    account_id: AccountId,
+    user_agent: Option<String>,
+    remote: Option<SocketAddr>,
+) -> Result<String, Error> {
+    let expires_on = Utc::now() + chrono::Duration::days(1);
+    let session_id = store
+        .add_session(
+            &account_id,
+            user_agent,
+            remote.map(|addr| addr.ip().to_string()),
+            expires_on,
+        )
+        .await?;
+    Ok(issue_token(account_id, session_id, expires_on))
 }
 
-fn issue_token(account_id: AccountId) -> String {
-    let key = env::var("PASETO_KEY").unwrap();
-    let current_date_time = Utc::now();
-    let dt = current_date_time + chrono::Duration::days(1);
+fn issue_token(account_id: AccountId, session_id: SessionId, exp: DateTime<Utc>) -> String {
+    let key = config::get().auth.paseto_key.expose();
 
     paseto::tokens::PasetoBuilder::new()
         .set_encryption_key(&Vec::from(key.as_bytes()))
-        .set_expiration(&dt)
+        .set_expiration(&exp)
         .set_not_before(&Utc::now())
         .set_claim("account_id", serde_json::json!(account_id))
+        .set_claim("session_id", serde_json::json!(session_id))
         .build()
         .expect("Failed to construct paseto token w/ builder!")
 }
 
-#[instrument]
-pub fn auth() -> impl Filter<Extract = (Session,), Error = warp::Rejection> + Clone {
-    warp::header::<String>("Authorization").and_then(|token: String| {
-        let token = match verify_token(token) {
-            Ok(t) => t,
-            Err(e) => {
-                tracing::event!(target:"book", Level::ERROR, "error when auth {:?}", e);
-                return future::ready(Err(warp::reject::custom(e)));
-            }
-        };
-        future::ready(Ok(token))
+/// API keys start with this so `auth` can tell them apart from PASETO tokens.
+const API_KEY_PREFIX: &str = "qa_";
+/// How many characters of a key are stored in plain to tell keys apart.
+pub const API_KEY_DISPLAY_LEN: usize = 10;
+
+/// Generates a new random API key.
+pub fn generate_api_key() -> String {
+    let secret: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(40)
+        .map(char::from)
+        .collect();
+    format!("{}{}", API_KEY_PREFIX, secret)
+}
+
+/// Hashes an API key for storage and lookup.
+///
+/// Keys are long and random, so unlike passwords a fast unsalted hash is enough.
+pub fn hash_api_key(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+/// Authenticates a request with either a PASETO token issued by `login` or an API key.
+///
+/// Credentials are read from `Authorization: Bearer <token>`, a bare `Authorization: <token>` or
+/// `X-Api-Key: <key>`.
+#[instrument(skip_all)]
+pub fn auth(store: Store) -> impl Filter<Extract = (Session,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("Authorization")
+        .and(warp::header::optional::<String>("X-Api-Key"))
+        .and_then(
+            move |authorization: Option<String>, api_key: Option<String>| {
+                let store = store.clone();
+                async move {
+                    authenticate(&store, authorization, api_key)
+                        .await
+                        .map_err(warp::reject::custom)
+                }
+            },
+        )
+}
+
+/// Like `auth`, but lets requests without any credentials through with None.
+///
+/// Credentials that are sent still have to be valid.
+#[instrument(skip_all)]
+pub fn optional_auth(
+    store: Store,
+) -> impl Filter<Extract = (Option<Session>,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("Authorization")
+        .and(warp::header::optional::<String>("X-Api-Key"))
+        .and_then(
+            move |authorization: Option<String>, api_key: Option<String>| {
+                let store = store.clone();
+                async move {
+                    if authorization.is_none() && api_key.is_none() {
+                        return Ok(None);
+                    }
+                    authenticate(&store, authorization, api_key)
+                        .await
+                        .map(Some)
+                        .map_err(warp::reject::custom)
+                }
+            },
+        )
+}
+
+async fn authenticate(
+    store: &Store,
+    authorization: Option<String>,
+    api_key: Option<String>,
+) -> Result<Session, Error> {
+    let token = match (api_key, authorization) {
+        (Some(key), _) => key,
+        (None, Some(authorization)) => match authorization.strip_prefix("Bearer ") {
+            Some(token) => token.to_string(),
+            None => authorization,
+        },
+        (None, None) => return Err(Error::MissingCredentials),
+    };
+
+    let session = if token.starts_with(API_KEY_PREFIX) {
+        verify_api_key(store, &token).await
+    } else {
+        verify_token(store, token).await
+    };
+    session.inspect_err(|e| {
+        tracing::event!(target:"book", Level::ERROR, "error when auth {:?}", e);
     })
 }
 
-#[instrument]
-fn verify_token(token: String) -> Result<Session, handle_errors::Error> {
-    let key = env::var("PASETO_KEY").unwrap();
+async fn verify_api_key(store: &Store, key: &str) -> Result<Session, Error> {
+    match store.use_api_key(&hash_api_key(key)).await? {
+        Some((account_id, api_key)) => Ok(Session {
+            exp: api_key.expires_on.unwrap_or(DateTime::<Utc>::MAX_UTC),
+            account_id,
+            nbf: api_key.created_on,
+            scopes: Some(api_key.scopes),
+            session_id: None,
+            api_key_id: Some(api_key.id),
+        }),
+        None => Err(Error::InvalidApiKey),
+    }
+}
+
+/// Decrypts a token from `login` and checks its session hasn't been revoked.
+#[instrument(skip_all)]
+async fn verify_token(store: &Store, token: String) -> Result<Session, handle_errors::Error> {
+    let key = config::get().auth.paseto_key.expose();
     let token = paseto::tokens::validate_local_token(
         &token,
         None,
@@ -102,5 +330,36 @@ fn verify_token(token: String) -> Result<Session, handle_errors::Error> {
         handle_errors::Error::CannotDecryptToken
     })?;
 
-    serde_json::from_value::<Session>(token).map_err(|_| handle_errors::Error::CannotDecryptToken)
+    let session = serde_json::from_value::<Session>(token)
+        .map_err(|_| handle_errors::Error::CannotDecryptToken)?;
+    match &session.session_id {
+        Some(session_id) if store.touch_session(session_id, &session.account_id).await? => {
+            Ok(session)
+        }
+        _ => Err(handle_errors::Error::SessionRevoked),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lockout_doubles_from_the_base() {
+        let secs: Vec<i64> = (5..10).map(|failures| lockout_secs(failures, 5)).collect();
+        assert_eq!(secs, [30, 60, 120, 240, 480]);
+    }
+
+    #[test]
+    fn lockout_is_capped() {
+        // 30s doubled 7 times is over the hour.
+        assert_eq!(lockout_secs(5 + 6, 5), 1920);
+        assert_eq!(lockout_secs(5 + 7, 5), LOCKOUT_MAX_SECS);
+        assert_eq!(lockout_secs(i32::MAX, 5), LOCKOUT_MAX_SECS);
+    }
+
+    #[test]
+    fn lockout_below_the_limit_is_the_base() {
+        assert_eq!(lockout_secs(0, 5), LOCKOUT_BASE_SECS);
+    }
 }
@@ -0,0 +1,48 @@
+use std::sync::{Arc, OnceLock};
+
+use utoipa_swagger_ui::Config;
+use warp::http::{StatusCode, Uri};
+use warp::path::{FullPath, Tail};
+use warp::Reply;
+
+use crate::openapi;
+
+/// The OpenAPI document for this API.
+#[utoipa::path(
+    get,
+    path = "/openapi.json",
+    tag = "operations",
+    responses((status = 200, description = "OpenAPI 3.1 document", body = Object))
+)]
+pub async fn openapi_json() -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::json(&openapi::document()))
+}
+
+/// Serves Swagger UI for `/openapi.json` under `/docs/`, from assets built into the binary.
+pub async fn swagger_ui(
+    full_path: FullPath,
+    tail: Tail,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    // Relative asset paths only work with the trailing slash.
+    if full_path.as_str() == "/docs" {
+        return Ok(warp::redirect::temporary(Uri::from_static("/docs/")).into_response());
+    }
+
+    static CONFIG: OnceLock<Arc<Config<'static>>> = OnceLock::new();
+    let config = CONFIG
+        .get_or_init(|| Arc::new(Config::from("/openapi.json")))
+        .clone();
+    match utoipa_swagger_ui::serve(tail.as_str(), config) {
+        Ok(Some(file)) => {
+            Ok(
+                warp::reply::with_header(file.bytes.to_vec(), "content-type", file.content_type)
+                    .into_response(),
+            )
+        }
+        Ok(None) => Err(warp::reject::not_found()),
+        Err(e) => Ok(
+            warp::reply::with_status(e.to_string(), StatusCode::INTERNAL_SERVER_ERROR)
+                .into_response(),
+        ),
+    }
+}
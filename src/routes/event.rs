@@ -0,0 +1,261 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use futures_util::{future, stream, SinkExt, StreamExt};
+use tokio::sync::broadcast;
+use tokio::time::{interval_at, Instant, Interval};
+use tokio_stream::wrappers::{BroadcastStream, IntervalStream};
+use warp::ws::{Message, WebSocket, Ws};
+
+use crate::events::Events;
+use crate::server::Shutdown;
+use crate::store::Store;
+use crate::types::account::Session;
+use crate::types::api_key::Scope;
+use crate::types::event::{Event, EventFilter};
+use crate::types::tag;
+
+use handle_errors::{Error, ErrorBody};
+
+/// How often the session of a client is checked again, to end its stream once revoked.
+const SESSION_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Streams changes to questions and answers as Server-Sent Events, named by their `kind`.
+///
+/// Logged in clients, and API keys with the read scope, also get their notifications. Their
+/// stream ends when the session expires or is revoked. Clients that fall too far behind miss
+/// events. Every stream ends when the server shuts down.
+#[utoipa::path(
+    get,
+    path = "/events",
+    tag = "events",
+    params(
+        ("tag" = Option<String>, Query, description = "Only events of questions with the tag"),
+        ("question_id" = Option<i32>, Query, description = "Only events of the question"),
+    ),
+    security((), ("token" = []), ("api_key" = ["read"])),
+    responses(
+        (status = 200, description = "Stream of events", body = Event, content_type = "text/event-stream"),
+        (status = 401, description = "Invalid credentials", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn events(
+    filter: EventFilter,
+    session: Option<Session>,
+    store: Store,
+    events: Events,
+    shutdown: Shutdown,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let filter = canonical_filter(filter, &store).await?;
+    let session = notified(session);
+    let lifetime = lifetime(session.as_ref());
+
+    // None is a tick of the session check.
+    let events = BroadcastStream::new(events.subscribe())
+        .filter_map(|event| future::ready(event.ok().map(Some)));
+    let checks = IntervalStream::new(session_checks()).map(|_| None);
+    let stream = stream::select(events, checks)
+        .then(move |event| {
+            let (filter, session, store) = (filter.clone(), session.clone(), store.clone());
+            async move {
+                let delivery = match &event {
+                    Some(event) => delivery(event, &filter, session.as_ref(), &store).await,
+                    None => check(session.as_ref(), &store).await,
+                };
+                (event, delivery)
+            }
+        })
+        .take_while(|(_, delivery)| future::ready(*delivery != Delivery::Disconnect))
+        .filter_map(|(event, delivery)| {
+            let event = event.filter(|_| delivery == Delivery::Send).map(|event| {
+                warp::sse::Event::default()
+                    .event(event.name())
+                    .json_data(&event)
+            });
+            future::ready(event)
+        })
+        .take_until(tokio::time::sleep(lifetime))
+        // Or the drain on shutdown would wait for the stream until its deadline.
+        .take_until(shutdown.wait());
+    Ok(warp::sse::reply(warp::sse::keep_alive().stream(stream)))
+}
+
+/// Same as `/events` over a WebSocket, each event is sent as a JSON text message.
+///
+/// Messages from the client are ignored. The socket is closed when the session expires or is
+/// revoked, and when the server shuts down.
+#[utoipa::path(
+    get,
+    path = "/events/ws",
+    tag = "events",
+    params(
+        ("tag" = Option<String>, Query, description = "Only events of questions with the tag"),
+        ("question_id" = Option<i32>, Query, description = "Only events of the question"),
+    ),
+    security((), ("token" = []), ("api_key" = ["read"])),
+    responses(
+        (status = 101, description = "Switched to a WebSocket sending events", body = Event),
+        (status = 401, description = "Invalid credentials", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn events_ws(
+    ws: Ws,
+    filter: EventFilter,
+    session: Option<Session>,
+    store: Store,
+    events: Events,
+    shutdown: Shutdown,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let filter = canonical_filter(filter, &store).await?;
+    let session = notified(session);
+    let receiver = events.subscribe();
+    Ok(ws.on_upgrade(move |socket| forward(socket, receiver, filter, session, store, shutdown)))
+}
+
+async fn forward(
+    socket: WebSocket,
+    receiver: broadcast::Receiver<Event>,
+    filter: EventFilter,
+    session: Option<Session>,
+    store: Store,
+    shutdown: Shutdown,
+) {
+    let (mut sender, mut messages) = socket.split();
+    let mut events = BroadcastStream::new(receiver);
+    let expired = tokio::time::sleep(lifetime(session.as_ref()));
+    let shutting_down = shutdown.wait();
+    tokio::pin!(expired, shutting_down);
+    let mut checks = session_checks();
+    loop {
+        tokio::select! {
+            event = events.next() => match event {
+                Some(Ok(event)) => match delivery(&event, &filter, session.as_ref(), &store).await {
+                    Delivery::Send => {
+                        let Ok(text) = serde_json::to_string(&event) else {
+                            continue;
+                        };
+                        if sender.send(Message::text(text)).await.is_err() {
+                            break;
+                        }
+                    }
+                    Delivery::Skip => {}
+                    Delivery::Disconnect => {
+                        let _ = sender.send(Message::close_with(1008u16, "session revoked")).await;
+                        break;
+                    }
+                },
+                // Missed by falling behind.
+                Some(Err(_)) => {}
+                None => break,
+            },
+            message = messages.next() => match message {
+                Some(Ok(message)) if !message.is_close() => {}
+                _ => break,
+            },
+            _ = checks.tick() => {
+                if check(session.as_ref(), &store).await == Delivery::Disconnect {
+                    let _ = sender.send(Message::close_with(1008u16, "session revoked")).await;
+                    break;
+                }
+            }
+            _ = &mut expired => {
+                let _ = sender.send(Message::close_with(1008u16, "session expired")).await;
+                break;
+            }
+            _ = &mut shutting_down => {
+                let _ = sender.send(Message::close_with(1001u16, "going away")).await;
+                break;
+            }
+        }
+    }
+}
+
+/// The session whose notifications a client gets, API keys need the read scope for them.
+fn notified(session: Option<Session>) -> Option<Session> {
+    session.filter(|session| session.allows(Scope::Read))
+}
+
+/// How long a client's stream lasts: until its session expires, or for good without one.
+fn lifetime(session: Option<&Session>) -> Duration {
+    match session {
+        Some(session) => (session.exp - Utc::now()).to_std().unwrap_or_default(),
+        None => Duration::MAX,
+    }
+}
+
+/// What to do with an event for a client.
+#[derive(Debug, PartialEq)]
+enum Delivery {
+    Send,
+    Skip,
+    /// The session was revoked, its stream ends.
+    Disconnect,
+}
+
+/// Returns whether event goes to a client subscribed with filter and logged in as session.
+/// Sessions are checked again before notifications are sent, so revoking a session or deleting an
+/// API key stops them right away, other events stop at the next check.
+async fn delivery(
+    event: &Event,
+    filter: &EventFilter,
+    session: Option<&Session>,
+    store: &Store,
+) -> Delivery {
+    if !event.matches(filter, session.map(|session| &session.account_id)) {
+        return Delivery::Skip;
+    }
+    let Some(session) = session.filter(|_| matches!(event, Event::Notification { .. })) else {
+        return Delivery::Send;
+    };
+    if revoked(session, store).await {
+        Delivery::Disconnect
+    } else {
+        Delivery::Send
+    }
+}
+
+/// Returns what to do on a tick of the session check, Disconnect once the session was revoked.
+async fn check(session: Option<&Session>, store: &Store) -> Delivery {
+    match session {
+        Some(session) if revoked(session, store).await => Delivery::Disconnect,
+        _ => Delivery::Skip,
+    }
+}
+
+/// Ticks every `SESSION_CHECK_INTERVAL`, starting one interval from now.
+fn session_checks() -> Interval {
+    interval_at(
+        Instant::now() + SESSION_CHECK_INTERVAL,
+        SESSION_CHECK_INTERVAL,
+    )
+}
+
+/// Returns whether session was revoked, or its API key deleted, as far as the store can tell.
+async fn revoked(session: &Session, store: &Store) -> bool {
+    let active = match (&session.session_id, &session.api_key_id) {
+        (Some(session_id), _) => store.touch_session(session_id, &session.account_id).await,
+        (None, Some(key_id)) => store.is_api_key_active(key_id, &session.account_id).await,
+        (None, None) => Ok(false),
+    };
+    // The store logs its errors. They keep the session, or a DB blip would drop every stream and
+    // have them all reconnect at once.
+    matches!(active, Ok(false))
+}
+
+/// Normalizes the tag filtered on and replaces a synonym by its tag, as events carry the
+/// canonical names.
+async fn canonical_filter(filter: EventFilter, store: &Store) -> Result<EventFilter, Error> {
+    let tag = match filter.tag {
+        Some(name) => {
+            let name = tag::normalize(&name);
+            match store.get_tag(&name).await? {
+                Some(tag) => Some(tag.name),
+                None => Some(name),
+            }
+        }
+        None => None,
+    };
+    Ok(EventFilter { tag, ..filter })
+}
@@ -0,0 +1,201 @@
+use crate::routes::tag::tag_name;
+use crate::store::Store;
+use crate::types::account::Session;
+use crate::types::api_key::Scope;
+use crate::types::follow::{FeedItem, FeedQuery, Follows};
+
+use handle_errors::{Error, ErrorBody};
+
+/// How many feed items are returned without a limit, and at most.
+const DEFAULT_LIMIT: i64 = 20;
+const MAX_LIMIT: i64 = 100;
+
+/// Lists the tags and questions the account follows.
+#[utoipa::path(
+    get,
+    path = "/accounts/me/follows",
+    tag = "accounts",
+    security(("token" = []), ("api_key" = ["read"])),
+    responses(
+        (status = 200, description = "What the account follows", body = Follows),
+        (status = 401, description = "Not logged in, or an API key without the read scope", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn get_follows(
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if !session.allows(Scope::Read) {
+        return Err(warp::reject::custom(Error::Unauthorized));
+    }
+    let follows = store.get_follows(&session.account_id).await?;
+    Ok(warp::reply::json(&follows))
+}
+
+/// Follows a tag, by its name or a synonym, to see new questions in it in the feed.
+#[utoipa::path(
+    put,
+    path = "/accounts/me/follows/tags/{name}",
+    tag = "accounts",
+    params(("name" = String, Path, description = "Name or synonym of the tag")),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "What the account follows now", body = Follows),
+        (status = 401, description = "Not logged in with a password", body = ErrorBody),
+        (status = 404, description = "No such tag", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn follow_tag(
+    name: String,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if !session.is_login() {
+        return Err(warp::reject::custom(Error::Unauthorized));
+    }
+    let name = tag_name(&name);
+    let tag = store
+        .get_tag(&name)
+        .await?
+        .ok_or_else(|| Error::NotFound(format!("tag {}", name)))?;
+
+    if let Some(e) = store.follow_tag(&session.account_id, &tag.name).await {
+        return Err(warp::reject::custom(e));
+    }
+    let follows = store.get_follows(&session.account_id).await?;
+    Ok(warp::reply::json(&follows))
+}
+
+/// Stops following a tag.
+#[utoipa::path(
+    delete,
+    path = "/accounts/me/follows/tags/{name}",
+    tag = "accounts",
+    params(("name" = String, Path, description = "Name or synonym of the tag")),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "What the account follows now", body = Follows),
+        (status = 401, description = "Not logged in with a password", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn unfollow_tag(
+    name: String,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if !session.is_login() {
+        return Err(warp::reject::custom(Error::Unauthorized));
+    }
+    let name = tag_name(&name);
+    // Synonyms name the tag that was followed.
+    let name = match store.get_tag(&name).await? {
+        Some(tag) => tag.name,
+        None => name,
+    };
+
+    if let Some(e) = store.unfollow_tag(&session.account_id, &name).await {
+        return Err(warp::reject::custom(e));
+    }
+    let follows = store.get_follows(&session.account_id).await?;
+    Ok(warp::reply::json(&follows))
+}
+
+/// Follows a question to see new answers to it in the feed.
+#[utoipa::path(
+    put,
+    path = "/accounts/me/follows/questions/{id}",
+    tag = "accounts",
+    params(("id" = i32, Path, description = "Question id")),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "What the account follows now", body = Follows),
+        (status = 401, description = "Not logged in with a password", body = ErrorBody),
+        (status = 404, description = "No such question", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn follow_question(
+    id: i32,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if !session.is_login() {
+        return Err(warp::reject::custom(Error::Unauthorized));
+    }
+    if store.get_questions_by_ids(&[id]).await?.is_empty() {
+        return Err(warp::reject::custom(Error::NotFound(format!(
+            "question {}",
+            id
+        ))));
+    }
+
+    if let Some(e) = store.follow_question(&session.account_id, id).await {
+        return Err(warp::reject::custom(e));
+    }
+    let follows = store.get_follows(&session.account_id).await?;
+    Ok(warp::reply::json(&follows))
+}
+
+/// Stops following a question.
+#[utoipa::path(
+    delete,
+    path = "/accounts/me/follows/questions/{id}",
+    tag = "accounts",
+    params(("id" = i32, Path, description = "Question id")),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "What the account follows now", body = Follows),
+        (status = 401, description = "Not logged in with a password", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn unfollow_question(
+    id: i32,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if !session.is_login() {
+        return Err(warp::reject::custom(Error::Unauthorized));
+    }
+
+    if let Some(e) = store.unfollow_question(&session.account_id, id).await {
+        return Err(warp::reject::custom(e));
+    }
+    let follows = store.get_follows(&session.account_id).await?;
+    Ok(warp::reply::json(&follows))
+}
+
+/// New questions in followed tags and new answers to followed questions, newest first.
+///
+/// The account's own questions and answers are left out.
+#[utoipa::path(
+    get,
+    path = "/feed",
+    tag = "accounts",
+    params(
+        ("limit" = Option<i64>, Query, description = "How many items to return, 20 by default and 100 at most"),
+        ("offset" = Option<i64>, Query, description = "How many items to skip"),
+    ),
+    security(("token" = []), ("api_key" = ["read"])),
+    responses(
+        (status = 200, description = "The feed, newest first", body = [FeedItem]),
+        (status = 401, description = "Not logged in, or an API key without the read scope", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn get_feed(
+    query: FeedQuery,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if !session.allows(Scope::Read) {
+        return Err(warp::reject::custom(Error::Unauthorized));
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let offset = query.offset.unwrap_or(0).max(0);
+    let feed = store.get_feed(&session.account_id, limit, offset).await?;
+    Ok(warp::reply::json(&feed))
+}
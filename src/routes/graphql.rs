@@ -0,0 +1,34 @@
+use handle_errors::ErrorBody;
+
+use crate::graphql::{self, BookSchema};
+use crate::store::Store;
+use crate::types::account::Session;
+
+/// Executes a GraphQL query or mutation against the schema in `graphql`.
+///
+/// Queries don't need credentials, mutations take the same ones as the REST routes.
+/// Errors of the operation are reported in the `errors` of the 200 reply.
+#[utoipa::path(
+    post,
+    path = "/graphql",
+    tag = "graphql",
+    request_body(content = Object, description = "GraphQL request with query, variables and operationName"),
+    security((), ("token" = []), ("api_key" = [])),
+    responses(
+        (status = 200, description = "GraphQL response with data and errors", body = Object),
+        (status = 401, description = "Invalid token or API key", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn graphql(
+    session: Option<Session>,
+    store: Store,
+    schema: BookSchema,
+    request: async_graphql::Request,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut request = request.data(graphql::loader(store));
+    if let Some(session) = session {
+        request = request.data(session);
+    }
+    Ok(warp::reply::json(&schema.execute(request).await))
+}
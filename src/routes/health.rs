@@ -0,0 +1,90 @@
+use crate::profanity::{self, CircuitState};
+use crate::store::Store;
+use crate::types::health::{
+    Check, CheckStatus, ContentFilterCheck, MigrationsCheck, Readiness, ReadinessChecks,
+};
+
+use warp::http::StatusCode;
+
+/// Liveness probe, answers as long as the process serves requests.
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "operations",
+    responses((status = 200, description = "The process is up", body = String))
+)]
+pub async fn healthz() -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::with_status("ok", StatusCode::OK))
+}
+
+/// Readiness probe, checks the DB is reachable and fully migrated.
+///
+/// Replies 503 with the breakdown of checks when not ready.
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "operations",
+    responses(
+        (status = 200, description = "Ready to serve requests", body = Readiness),
+        (status = 503, description = "The DB is down or migrations are pending", body = Readiness),
+    )
+)]
+pub async fn readyz(store: Store) -> Result<impl warp::Reply, warp::Rejection> {
+    let (ping, pending) = tokio::join!(store.ping(), store.pending_migrations());
+
+    let database = match ping {
+        None => Check {
+            status: CheckStatus::Ok,
+            error: None,
+        },
+        Some(e) => Check {
+            status: CheckStatus::Failed,
+            error: Some(e.to_string()),
+        },
+    };
+    let migrations = match pending {
+        Ok(pending) if pending.is_empty() => MigrationsCheck {
+            status: CheckStatus::Ok,
+            pending,
+            error: None,
+        },
+        Ok(pending) => MigrationsCheck {
+            status: CheckStatus::Failed,
+            pending,
+            error: None,
+        },
+        Err(e) => MigrationsCheck {
+            status: CheckStatus::Failed,
+            pending: vec![],
+            error: Some(e.to_string()),
+        },
+    };
+    // Taken from the calls made anyway, probing the API would use up its quota.
+    let circuit = profanity::circuit_state();
+    let content_filter = ContentFilterCheck {
+        status: if circuit != CircuitState::Open {
+            CheckStatus::Ok
+        } else {
+            CheckStatus::Failed
+        },
+        circuit,
+    };
+
+    let ready = database.status == CheckStatus::Ok && migrations.status == CheckStatus::Ok;
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    Ok(warp::reply::with_status(
+        warp::reply::json(&Readiness {
+            ready,
+            checks: ReadinessChecks {
+                database,
+                migrations,
+                content_filter,
+            },
+        }),
+        status,
+    ))
+}
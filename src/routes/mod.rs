@@ -1,3 +1,15 @@
 pub mod answer;
+pub mod api_key;
 pub mod authentication;
+pub mod docs;
+pub mod event;
+pub mod follow;
+pub mod graphql;
+pub mod health;
+pub mod notification;
+pub mod oidc;
 pub mod question;
+pub mod revision;
+pub mod session;
+pub mod tag;
+pub mod v2;
@@ -0,0 +1,127 @@
+use crate::store::Store;
+use crate::types::account::Session;
+use crate::types::api_key::Scope;
+use crate::types::notification::{Notification, NotificationQuery, UnreadCount};
+
+use handle_errors::{Error, ErrorBody};
+
+/// How many notifications are returned without a limit, and at most.
+const DEFAULT_LIMIT: i64 = 20;
+const MAX_LIMIT: i64 = 100;
+
+/// Lists the account's notifications, newest first.
+#[utoipa::path(
+    get,
+    path = "/accounts/me/notifications",
+    tag = "accounts",
+    params(
+        ("unread" = Option<bool>, Query, description = "Only unread notifications"),
+        ("limit" = Option<i64>, Query, description = "How many to return, 20 by default and 100 at most"),
+        ("offset" = Option<i64>, Query, description = "How many to skip"),
+    ),
+    security(("token" = []), ("api_key" = ["read"])),
+    responses(
+        (status = 200, description = "The account's notifications", body = [Notification]),
+        (status = 401, description = "Not logged in, or an API key without the read scope", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn get_notifications(
+    query: NotificationQuery,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if !session.allows(Scope::Read) {
+        return Err(warp::reject::custom(Error::Unauthorized));
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let offset = query.offset.unwrap_or(0).max(0);
+    let notifications = store
+        .get_notifications(&session.account_id, query.unread, limit, offset)
+        .await?;
+    Ok(warp::reply::json(&notifications))
+}
+
+/// Returns how many of the account's notifications are unread.
+#[utoipa::path(
+    get,
+    path = "/accounts/me/notifications/unread_count",
+    tag = "accounts",
+    security(("token" = []), ("api_key" = ["read"])),
+    responses(
+        (status = 200, description = "Number of unread notifications", body = UnreadCount),
+        (status = 401, description = "Not logged in, or an API key without the read scope", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn get_unread_count(
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if !session.allows(Scope::Read) {
+        return Err(warp::reject::custom(Error::Unauthorized));
+    }
+    let unread = store.get_unread_count(&session.account_id).await?;
+    Ok(warp::reply::json(&UnreadCount { unread }))
+}
+
+/// Marks one of the account's notifications as read.
+#[utoipa::path(
+    post,
+    path = "/accounts/me/notifications/{id}/read",
+    tag = "accounts",
+    params(("id" = i32, Path, description = "Notification id")),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The notification", body = Notification),
+        (status = 401, description = "Not logged in with a password", body = ErrorBody),
+        (status = 404, description = "No such notification of the account", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn mark_read(
+    id: i32,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if !session.is_login() {
+        return Err(warp::reject::custom(Error::Unauthorized));
+    }
+
+    match store
+        .mark_notification_read(id, &session.account_id)
+        .await?
+    {
+        Some(notification) => Ok(warp::reply::json(&notification)),
+        None => Err(warp::reject::custom(Error::NotFound(format!(
+            "notification {}",
+            id
+        )))),
+    }
+}
+
+/// Marks all of the account's notifications as read.
+#[utoipa::path(
+    post,
+    path = "/accounts/me/notifications/read",
+    tag = "accounts",
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "No notifications are unread now", body = UnreadCount),
+        (status = 401, description = "Not logged in with a password", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn mark_all_read(
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if !session.is_login() {
+        return Err(warp::reject::custom(Error::Unauthorized));
+    }
+
+    match store.mark_all_notifications_read(&session.account_id).await {
+        None => Ok(warp::reply::json(&UnreadCount { unread: 0 })),
+        Some(e) => Err(warp::reject::custom(e)),
+    }
+}
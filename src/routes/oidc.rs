@@ -0,0 +1,346 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use crate::oidc;
+use crate::password;
+use crate::routes::authentication::start_session;
+use crate::store::Store;
+use crate::types::account::{Account, AccountId};
+
+use handle_errors::{Error, ErrorBody};
+use rand::{distributions::Alphanumeric, Rng};
+use tracing::{event, Level};
+use warp::http::Uri;
+
+/// Cookie tying the `state` of a login to the browser that started it.
+const STATE_COOKIE: &str = "oidc_state";
+/// As long as the store keeps the state of a login.
+const STATE_MAX_AGE_SECS: i64 = 10 * 60;
+
+fn provider() -> Result<&'static oidc::Provider, Error> {
+    oidc::provider().ok_or_else(|| Error::NotFound("OIDC login".to_string()))
+}
+
+/// The `Set-Cookie` value for the state cookie, an empty value with max_age 0 removes it.
+///
+/// The cookie is `SameSite=Lax` as the provider redirects to the callback from another site.
+fn state_cookie(provider: &oidc::Provider, value: &str, max_age: i64) -> String {
+    let secure = if provider.redirect_url.starts_with("https://") {
+        "; Secure"
+    } else {
+        ""
+    };
+    format!(
+        "{}={}; Max-Age={}; Path=/; HttpOnly; SameSite=Lax{}",
+        STATE_COOKIE, value, max_age, secure
+    )
+}
+
+/// Starts an SSO login by redirecting to the OIDC provider.
+///
+/// The login's `state` is also set as a cookie, the callback only accepts it from the same
+/// browser.
+#[utoipa::path(
+    get,
+    path = "/oidc/login",
+    tag = "accounts",
+    responses(
+        (status = 307, description = "Redirect to the provider's login page",
+            headers(("Set-Cookie" = String, description = "The `oidc_state` cookie"))),
+        (status = 404, description = "SSO isn't configured", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn oidc_login(store: Store) -> Result<impl warp::Reply, warp::Rejection> {
+    let provider = provider()?;
+    let request = oidc::authorization_request(provider).await?;
+
+    if let Some(e) = store
+        .add_oidc_login(&request.state, &request.code_verifier)
+        .await
+    {
+        return Err(warp::reject::custom(e));
+    }
+
+    let uri = request
+        .url
+        .parse::<Uri>()
+        .map_err(|e| Error::OidcError(format!("bad authorization url: {}", e)))?;
+    Ok(warp::reply::with_header(
+        warp::redirect::temporary(uri),
+        "set-cookie",
+        state_cookie(provider, &request.state, STATE_MAX_AGE_SECS),
+    ))
+}
+
+/// Completes an SSO login, the provider redirects here with `code` and `state`.
+///
+/// The `state` has to match the cookie set by `oidc_login`, so a login can't be completed in
+/// someone else's browser. The external identity is linked to the account with the same verified
+/// email, or to a new account if there is none. Replies with a token just like `login`.
+#[utoipa::path(
+    get,
+    path = "/oidc/callback",
+    tag = "accounts",
+    params(
+        ("code" = Option<String>, Query, description = "Authorization code"),
+        ("state" = Option<String>, Query, description = "State from the login redirect"),
+        ("error" = Option<String>, Query, description = "Set by the provider if login failed"),
+        ("oidc_state" = Option<String>, Cookie, description = "Set by the login redirect"),
+    ),
+    responses(
+        (status = 200, description = "Token to send as `Authorization: Bearer`", body = String),
+        (status = 401, description = "Login failed, or the state is unknown or not this browser's", body = ErrorBody),
+        (status = 404, description = "SSO isn't configured", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn oidc_callback(
+    store: Store,
+    remote: Option<SocketAddr>,
+    user_agent: Option<String>,
+    cookie: Option<String>,
+    params: HashMap<String, String>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let provider = provider()?;
+    if let Some(error) = params.get("error") {
+        return Err(warp::reject::custom(Error::OidcError(format!(
+            "provider returned {}",
+            error
+        ))));
+    }
+    let (code, state) = match (params.get("code"), params.get("state")) {
+        (Some(code), Some(state)) => (code, state),
+        _ => return Err(warp::reject::custom(Error::MissingParameters)),
+    };
+    if cookie.as_ref() != Some(state) {
+        return Err(warp::reject::custom(Error::OidcError(
+            "state doesn't match the browser's".to_string(),
+        )));
+    }
+
+    let code_verifier = match store.take_oidc_login(state).await? {
+        Some(code_verifier) => code_verifier,
+        None => {
+            return Err(warp::reject::custom(Error::OidcError(
+                "unknown or expired state".to_string(),
+            )))
+        }
+    };
+    let user = oidc::exchange_code(provider, code, &code_verifier).await?;
+
+    let account_id = match store
+        .get_identity_account(&provider.issuer, &user.sub)
+        .await?
+    {
+        Some(account_id) => account_id,
+        None => link_identity(&store, provider, user).await?,
+    };
+
+    let token = start_session(&store, account_id, user_agent, remote).await?;
+    Ok(warp::reply::with_header(
+        warp::reply::json(&token),
+        "set-cookie",
+        state_cookie(provider, "", 0),
+    ))
+}
+
+/// Links a new external identity to the account with its email, creating one if needed.
+async fn link_identity(
+    store: &Store,
+    provider: &oidc::Provider,
+    user: oidc::UserInfo,
+) -> Result<AccountId, Error> {
+    let email = match user.email {
+        Some(email) => email,
+        None => {
+            return Err(Error::OidcError(
+                "provider didn't return an email".to_string(),
+            ))
+        }
+    };
+    // Only trust the provider to vouch for an email it verified. Creating an account for an
+    // unverified one would let its real owner be linked into it later.
+    if !user.email_verified {
+        return Err(Error::OidcError(format!(
+            "{} isn't verified by the provider",
+            email
+        )));
+    }
+
+    let account_id = match store.get_account(email.clone()).await? {
+        Some(account) => account.id.expect("id not found"),
+        None => {
+            // SSO accounts get a random password, they can only log in through the provider.
+            let random: String = rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(32)
+                .map(char::from)
+                .collect();
+            let account = Account {
+                id: None,
+                email: email.clone(),
+                password: password::hash(random).await?,
+            };
+            store.add_account(account).await?
+        }
+    };
+
+    if let Some(e) = store
+        .add_identity(&provider.issuer, &user.sub, &account_id)
+        .await
+    {
+        return Err(e);
+    }
+    event!(target: "book", Level::INFO, account_id = account_id.0, issuer = %provider.issuer, "linked external identity");
+    Ok(account_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::OnceLock;
+
+    use clap::Parser;
+    use warp::Reply;
+
+    use super::*;
+    use crate::config::{self, Args, Config};
+    use crate::mock_oidc;
+    use crate::store;
+
+    const UNVERIFIED_SUBJECT: &str = "mock-unverified-user";
+    const UNVERIFIED_EMAIL: &str = "unverified@example.com";
+
+    /// Starts the mock provider in examples/mock_oidc once for all tests, on its own runtime as
+    /// every test has one. Returns its issuer.
+    fn issuer() -> &'static str {
+        static ISSUER: OnceLock<String> = OnceLock::new();
+        ISSUER.get_or_init(|| {
+            let users = vec![
+                mock_oidc::User::default(),
+                mock_oidc::User {
+                    subject: UNVERIFIED_SUBJECT.to_string(),
+                    email: UNVERIFIED_EMAIL.to_string(),
+                    email_verified: false,
+                },
+            ];
+            let (tx, rx) = std::sync::mpsc::channel();
+            std::thread::spawn(move || {
+                tokio::runtime::Runtime::new().unwrap().block_on(async {
+                    let (addr, provider) = warp::serve(mock_oidc::routes_for(users))
+                        .bind_ephemeral(([127, 0, 0, 1], 0));
+                    tx.send(addr).unwrap();
+                    provider.await
+                })
+            });
+            format!("http://{}", rx.recv().unwrap())
+        })
+    }
+
+    /// Returns a store with the config pointing SSO to the mock provider.
+    async fn setup() -> Store {
+        let mut config = Config::load(&Args::parse_from(["book"])).expect("valid config");
+        config.oidc = Some(oidc::Provider {
+            issuer: issuer().to_string(),
+            client_id: "book".to_string(),
+            client_secret: None,
+            redirect_url: "http://localhost:3031/v2/oidc/callback".to_string(),
+        });
+        let config = config::init(config);
+        let store = Store::new(&config.database).await.unwrap();
+        store::MIGRATOR.run(&store.connection).await.unwrap();
+        store
+    }
+
+    /// Runs a login of subject against the mock provider up to the callback, returns the state
+    /// cookie and the params the provider redirected back with.
+    async fn login(store: &Store, subject: &str) -> (String, HashMap<String, String>) {
+        let reply = oidc_login(store.clone())
+            .await
+            .expect("login redirects to the provider")
+            .into_response();
+        let location = reply.headers()["location"].to_str().unwrap().to_string();
+        let cookie = reply.headers()["set-cookie"].to_str().unwrap();
+        let state = cookie
+            .split(';')
+            .next()
+            .and_then(|cookie| cookie.strip_prefix("oidc_state="))
+            .expect("state cookie is set")
+            .to_string();
+
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+        let res = client
+            .get(location)
+            .query(&[("login_hint", subject)])
+            .send()
+            .await
+            .unwrap();
+        let callback = reqwest::Url::parse(res.headers()["location"].to_str().unwrap()).unwrap();
+        (state, callback.query_pairs().into_owned().collect())
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the database and keys the server is started with, run with --ignored"]
+    async fn logs_in_through_the_provider() {
+        let store = setup().await;
+
+        // Another browser's state, as in login CSRF, isn't accepted.
+        let (state, params) = login(&store, mock_oidc::SUBJECT).await;
+        let (other_state, _) = login(&store, mock_oidc::SUBJECT).await;
+        for cookie in [None, Some(other_state)] {
+            let res = oidc_callback(store.clone(), None, None, cookie, params.clone()).await;
+            assert!(res.is_err());
+        }
+
+        let reply = oidc_callback(
+            store.clone(),
+            None,
+            None,
+            Some(state.clone()),
+            params.clone(),
+        )
+        .await
+        .expect("callback logs in")
+        .into_response();
+        assert!(reply.headers()["set-cookie"]
+            .to_str()
+            .unwrap()
+            .contains("Max-Age=0"));
+        let body = warp::hyper::body::to_bytes(reply.into_body())
+            .await
+            .unwrap();
+        assert!(serde_json::from_slice::<String>(&body).is_ok());
+        assert!(store
+            .get_identity_account(issuer(), mock_oidc::SUBJECT)
+            .await
+            .unwrap()
+            .is_some());
+
+        // The state can only be used once.
+        let res = oidc_callback(store.clone(), None, None, Some(state), params).await;
+        assert!(res.is_err());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the database and keys the server is started with, run with --ignored"]
+    async fn rejects_unverified_emails() {
+        let store = setup().await;
+
+        let (state, params) = login(&store, UNVERIFIED_SUBJECT).await;
+        let res = oidc_callback(store.clone(), None, None, Some(state), params).await;
+        assert!(res.is_err());
+        assert!(store
+            .get_account(UNVERIFIED_EMAIL.to_string())
+            .await
+            .unwrap()
+            .is_none());
+        assert!(store
+            .get_identity_account(issuer(), UNVERIFIED_SUBJECT)
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
@@ -1,16 +1,40 @@
+use crate::config;
+use crate::etag;
 use crate::profanity::check_profanity;
+use crate::routes::tag::canonical_tags;
 use crate::store::Store;
-use crate::types::account::Session;
+use crate::types::account::{AccountId, Session};
+use crate::types::api_key::Scope;
+use crate::types::notification::mentions;
 use crate::types::pagination::Pagination;
-use crate::types::question::NewQuestion;
+use crate::types::question::{NewQuestion, QuestionId};
 use crate::types::{pagination::extract_pagination, question::Question};
+use handle_errors::ErrorBody;
 use std::collections::HashMap;
 use tracing::{event, info, instrument, Level};
 use warp::http::StatusCode;
 
-#[instrument]
+#[instrument(skip(store))]
+#[utoipa::path(
+    get,
+    path = "/questions",
+    tag = "questions",
+    params(
+        ("limit" = Option<i32>, Query, description = "How many questions to return, needs offset"),
+        ("offset" = Option<i32>, Query, description = "How many questions to skip, needs limit"),
+        ("If-None-Match" = Option<String>, Header, description = "ETag of a previous reply"),
+    ),
+    responses(
+        (status = 200, description = "Questions", body = [Question],
+            headers(("ETag" = String, description = "Changes when a question is added, updated or deleted"))),
+        (status = 304, description = "Not modified since the reply with If-None-Match"),
+        (status = 422, description = "Only one of limit and offset, or not numbers", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
 pub async fn get_questions(
     params: HashMap<String, String>,
+    if_none_match: Option<String>,
     store: Store,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     event!(target: "book", Level::INFO, "querying questions");
@@ -31,90 +55,169 @@ pub async fn get_questions(
         Ok(res) => res,
         Err(e) => return Err(warp::reject::custom(e)),
     };
-    Ok(warp::reply::json(&res))
+    let etag = etag::of_versions(res.iter().map(|q| (q.id.0, q.version)));
+    Ok(etag::reply(warp::reply::json(&res), &etag, if_none_match))
 }
 
-#[instrument]
+#[instrument(skip_all, fields(account_id = session.account_id.0))]
+#[utoipa::path(
+    post,
+    path = "/questions",
+    tag = "questions",
+    request_body = NewQuestion,
+    security(("token" = []), ("api_key" = ["post_questions"])),
+    responses(
+        (status = 200, description = "Question added, with bad words censored", body = String),
+        (status = 401, description = "Not logged in, or the API key lacks the scope", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+        (status = 503, description = "Content filter unavailable", body = ErrorBody),
+    )
+)]
 pub async fn add_question(
     session: Session,
     store: Store,
     new_question: NewQuestion,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     event!(target: "book", Level::INFO, "adding question {:?}", new_question);
-    let title = match check_profanity(new_question.title).await {
-        Ok(res) => res,
-        Err(e) => return Err(warp::reject::custom(e)),
-    };
+    create_question(&session, &store, new_question).await?;
+    Ok(warp::reply::with_status("Question added", StatusCode::OK))
+}
 
-    let content = match check_profanity(new_question.content).await {
-        Ok(res) => res,
-        Err(e) => return Err(warp::reject::custom(e)),
-    };
+/// Censors and stores a question asked by session, shared by the versions of `add_question`.
+///
+/// Notifies the accounts mentioned in the question.
+pub async fn create_question(
+    session: &Session,
+    store: &Store,
+    new_question: NewQuestion,
+) -> Result<Question, handle_errors::Error> {
+    if !session.allows(Scope::PostQuestions) {
+        return Err(handle_errors::Error::Unauthorized);
+    }
+    let (title, content) = tokio::join!(
+        check_profanity(new_question.title),
+        check_profanity(new_question.content)
+    );
 
     let new_question = NewQuestion {
-        title,
-        content,
-        tags: new_question.tags,
+        title: title?,
+        content: content?,
+        tags: canonical_tags(store, new_question.tags).await?,
     };
 
-    if let Err(e) = store.add_question(new_question, session.account_id).await {
-        return Err(warp::reject::custom(e));
-    }
-
-    Ok(warp::reply::with_status("Question added", StatusCode::OK))
+    let question = store
+        .add_question(new_question, session.account_id.clone())
+        .await?;
+    // Not getting notified doesn't undo the question, the store logs why.
+    let text = format!("{} {}", question.title, question.content);
+    store
+        .add_notifications(question.id.0, None, &session.account_id, mentions(&text))
+        .await;
+    Ok(question)
 }
 
 /// Update handler for Question resource.
+///
+/// With `If-Match` the question is only updated if it's still at the version in the ETag.
+#[utoipa::path(
+    put,
+    path = "/questions/{id}",
+    tag = "questions",
+    params(
+        ("id" = i32, Path, description = "Question id"),
+        ("If-Match" = Option<String>, Header, description = "ETag the question was read with"),
+    ),
+    request_body = Question,
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The updated question", body = Question,
+            headers(("ETag" = String, description = "Version of the updated question"))),
+        (status = 401, description = "Not logged in, or not the owner", body = ErrorBody),
+        (status = 412, description = "Changed since the If-Match version", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+        (status = 503, description = "Content filter unavailable", body = ErrorBody),
+    )
+)]
 pub async fn update_question(
     id: i32,
     session: Session,
     store: Store,
+    if_match: Option<String>,
     question: Question,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    if !store.is_question_owner(id, &session.account_id).await? {
-        return Err(warp::reject::custom(handle_errors::Error::Unauthorized));
-    }
-
-    let title = check_profanity(question.title);
-    let content = check_profanity(question.content);
-
-    let (title, content) = tokio::join!(title, content);
+    let versions = if_match.as_deref().and_then(etag::versions_in);
+    let question = change_question(&session, &store, id, question, versions, None).await?;
+    Ok(warp::reply::with_header(
+        warp::reply::json(&question),
+        "ETag",
+        etag::of_version(question.version),
+    ))
+}
 
-    if title.is_err() {
-        return Err(warp::reject::custom(title.unwrap_err()));
+/// Censors and stores the new title, content and tags of the question id, as a revision with
+/// summary.
+///
+/// Only its owner may change it, and only if it's at one of versions if they're given.
+pub async fn change_question(
+    session: &Session,
+    store: &Store,
+    id: i32,
+    question: Question,
+    versions: Option<Vec<i32>>,
+    summary: Option<String>,
+) -> Result<Question, handle_errors::Error> {
+    if !session.is_login() || !store.is_question_owner(id, &session.account_id).await? {
+        return Err(handle_errors::Error::Unauthorized);
     }
-
-    if content.is_err() {
-        return Err(warp::reject::custom(content.unwrap_err()));
+    if versions.as_ref().is_some_and(|v| v.is_empty()) {
+        return Err(handle_errors::Error::PreconditionFailed);
     }
 
+    let (title, content) = tokio::join!(
+        check_profanity(question.title),
+        check_profanity(question.content)
+    );
+
     let question = Question {
-        id: question.id,
-        title: title.unwrap(),
-        content: content.unwrap(),
-        tags: question.tags,
+        id: QuestionId(id),
+        title: title?,
+        content: content?,
+        tags: canonical_tags(store, question.tags).await?,
+        version: question.version,
     };
 
-    match store
-        .update_question(question, id, session.account_id)
-        .await
-    {
-        Ok(res) => Ok(warp::reply::json(&res)),
-        Err(e) => Err(warp::reject::custom(e)),
-    }
+    store
+        .update_question(question, id, session.account_id.clone(), versions, summary)
+        .await?
+        .ok_or(handle_errors::Error::PreconditionFailed)
 }
 
 /// Delete handler for Question
+///
+/// The question and its answers are hidden, and can be restored within the configured restore
+/// window. They're purged for good after the configured retention.
+#[utoipa::path(
+    delete,
+    path = "/questions/{id}",
+    tag = "questions",
+    params(("id" = i32, Path, description = "Question id")),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "Question deleted", body = String),
+        (status = 401, description = "Not logged in, or not the owner", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
 pub async fn delete_question(
     id: i32,
     session: Session,
     store: Store,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    if !store.is_question_owner(id, &session.account_id).await? {
+    if !session.is_login() || !store.is_question_owner(id, &session.account_id).await? {
         return Err(warp::reject::custom(handle_errors::Error::Unauthorized));
     }
 
-    match store.delete_question(id).await {
+    match store.delete_question(id, &session.account_id).await {
         None => Ok(warp::reply::with_status(
             format!("Question {} deleted", id),
             StatusCode::OK,
@@ -122,3 +225,55 @@ pub async fn delete_question(
         Some(e) => Err(warp::reject::custom(e)),
     }
 }
+
+/// Restore handler for a deleted Question.
+///
+/// Its owner or a moderator may restore it within the configured window.
+#[utoipa::path(
+    post,
+    path = "/questions/{id}/restore",
+    tag = "questions",
+    params(("id" = i32, Path, description = "Question id")),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The restored question", body = Question,
+            headers(("ETag" = String, description = "Version of the question"))),
+        (status = 401, description = "Not logged in with a password", body = ErrorBody),
+        (status = 404, description = "No question of yours deleted within the window", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn restore_question(
+    id: i32,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let owner = restorable_by(&session, &store).await?;
+    let window = config::get().deletion.restore_window();
+    match store.restore_question(id, owner.as_ref(), window).await? {
+        Some(question) => Ok(warp::reply::with_header(
+            warp::reply::json(&question),
+            "ETag",
+            etag::of_version(question.version),
+        )),
+        None => Err(warp::reject::custom(handle_errors::Error::NotFound(
+            format!("deleted question {}", id),
+        ))),
+    }
+}
+
+/// Returns the account whose deleted questions and answers session may restore, or None if it
+/// may restore anyone's as a moderator.
+pub async fn restorable_by(
+    session: &Session,
+    store: &Store,
+) -> Result<Option<AccountId>, handle_errors::Error> {
+    if !session.is_login() {
+        return Err(handle_errors::Error::Unauthorized);
+    }
+    if store.is_moderator(&session.account_id).await? {
+        Ok(None)
+    } else {
+        Ok(Some(session.account_id.clone()))
+    }
+}
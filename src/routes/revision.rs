@@ -0,0 +1,97 @@
+use crate::etag;
+use crate::routes::tag::canonical_tags;
+use crate::store::Store;
+use crate::types::account::Session;
+use crate::types::question::Question;
+use crate::types::revision::{Revision, RevisionDiff};
+
+use handle_errors::{Error, ErrorBody};
+
+/// Lists the revisions of a question oldest first, each with its diff to the one before.
+#[utoipa::path(
+    get,
+    path = "/questions/{id}/revisions",
+    tag = "questions",
+    params(("id" = i32, Path, description = "Question id")),
+    responses(
+        (status = 200, description = "The revisions of the question", body = [Revision]),
+        (status = 404, description = "No such question", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn get_revisions(id: i32, store: Store) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut revisions = store.get_question_revisions(id).await?;
+    if revisions.is_empty() {
+        return Err(warp::reject::custom(Error::NotFound(format!(
+            "question {}",
+            id
+        ))));
+    }
+    for i in 1..revisions.len() {
+        revisions[i].diff = Some(RevisionDiff::between(&revisions[i - 1], &revisions[i]));
+    }
+    Ok(warp::reply::json(&revisions))
+}
+
+/// Sets a question back to one of its revisions, which is kept as a new revision.
+///
+/// The owner or a moderator may roll back. Requires `If-Match` like other edits.
+#[utoipa::path(
+    post,
+    path = "/questions/{id}/revisions/{version}/rollback",
+    tag = "questions",
+    params(
+        ("id" = i32, Path, description = "Question id"),
+        ("version" = i32, Path, description = "Version of the revision to roll back to"),
+        ("If-Match" = String, Header, description = "ETag the question was read with"),
+    ),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The rolled back question", body = Question,
+            headers(("ETag" = String, description = "Version of the rolled back question"))),
+        (status = 401, description = "Not logged in, or neither the owner nor a moderator", body = ErrorBody),
+        (status = 404, description = "No such question or revision", body = ErrorBody),
+        (status = 412, description = "Changed since the If-Match version", body = ErrorBody),
+        (status = 428, description = "Missing If-Match", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn rollback_question(
+    id: i32,
+    version: i32,
+    session: Session,
+    store: Store,
+    if_match: Option<String>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let if_match = if_match.ok_or(Error::PreconditionRequired)?;
+    if !session.is_login()
+        || !(store.is_question_owner(id, &session.account_id).await?
+            || store.is_moderator(&session.account_id).await?)
+    {
+        return Err(warp::reject::custom(Error::Unauthorized));
+    }
+    let versions = etag::versions_in(&if_match);
+    if versions.as_ref().is_some_and(|v| v.is_empty()) {
+        return Err(warp::reject::custom(Error::PreconditionFailed));
+    }
+
+    let revision = store
+        .get_question_revisions(id)
+        .await?
+        .into_iter()
+        .find(|revision| revision.version == version)
+        .ok_or_else(|| Error::NotFound(format!("revision {} of question {}", version, id)))?;
+    // Its tags may have been merged into others since.
+    let tags = canonical_tags(&store, revision.tags).await?;
+
+    let summary = format!("Rolled back to revision {}", version);
+    let question = store
+        .rollback_question(id, version, tags, &session.account_id, summary, versions)
+        .await?
+        .ok_or(Error::PreconditionFailed)?;
+    Ok(warp::reply::with_header(
+        warp::reply::json(&question),
+        "ETag",
+        etag::of_version(question.version),
+    ))
+}
@@ -0,0 +1,71 @@
+use crate::store::Store;
+use crate::types::account::{Session, SessionInfo};
+
+use handle_errors::{Error, ErrorBody};
+use warp::http::StatusCode;
+
+/// Lists the devices the logged in account has active sessions on.
+#[utoipa::path(
+    get,
+    path = "/accounts/me/sessions",
+    tag = "accounts",
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The account's active sessions", body = [SessionInfo]),
+        (status = 401, description = "Not logged in with a password", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn get_sessions(
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if !session.is_login() {
+        return Err(warp::reject::custom(Error::Unauthorized));
+    }
+
+    let mut sessions = match store.get_sessions(&session.account_id).await {
+        Ok(sessions) => sessions,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    for s in sessions.iter_mut() {
+        s.current = Some(&s.id) == session.session_id.as_ref();
+    }
+    Ok(warp::reply::json(&sessions))
+}
+
+/// Logs one of the account's sessions out, its token stops working right away.
+#[utoipa::path(
+    delete,
+    path = "/accounts/me/sessions/{id}",
+    tag = "accounts",
+    params(("id" = i32, Path, description = "Session id")),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "Session logged out", body = String),
+        (status = 401, description = "Not logged in with a password", body = ErrorBody),
+        (status = 404, description = "No such session", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn delete_session(
+    id: i32,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if !session.is_login() {
+        return Err(warp::reject::custom(Error::Unauthorized));
+    }
+
+    match store.revoke_session(id, &session.account_id).await {
+        Ok(true) => Ok(warp::reply::with_status(
+            format!("Session {} deleted", id),
+            StatusCode::OK,
+        )),
+        Ok(false) => Err(warp::reject::custom(Error::NotFound(format!(
+            "Session {}",
+            id
+        )))),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
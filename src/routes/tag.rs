@@ -0,0 +1,171 @@
+use crate::store::Store;
+use crate::types::account::Session;
+use crate::types::tag::{self, Tag, TagEdit, TagMerge, TagQuery};
+
+use handle_errors::{Error, ErrorBody};
+
+/// How many tags are listed without a limit, and at most.
+const DEFAULT_LIMIT: i64 = 20;
+const MAX_LIMIT: i64 = 100;
+
+/// Normalizes the tags of a question being asked or edited and replaces synonyms by their tags.
+pub async fn canonical_tags(
+    store: &Store,
+    tags: Option<Vec<String>>,
+) -> Result<Option<Vec<String>>, Error> {
+    let Some(tags) = tags else {
+        return Ok(None);
+    };
+    let names = tags
+        .iter()
+        .map(|name| tag::normalize(name))
+        .filter(|name| !name.is_empty())
+        .collect();
+    store.canonical_tags(names).await.map(Some)
+}
+
+/// Lists tags by how many questions have them, optionally only those starting with a prefix.
+#[utoipa::path(
+    get,
+    path = "/tags",
+    tag = "tags",
+    params(
+        ("prefix" = Option<String>, Query, description = "Only tags whose name or a synonym starts with it"),
+        ("limit" = Option<i64>, Query, description = "How many tags to return, 20 by default and 100 at most"),
+    ),
+    responses(
+        (status = 200, description = "Tags, most used first", body = [Tag]),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn get_tags(query: TagQuery, store: Store) -> Result<impl warp::Reply, warp::Rejection> {
+    let prefix = query.prefix.map(|prefix| tag::normalize(&prefix));
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let tags = store.get_tags(prefix, limit).await?;
+    Ok(warp::reply::json(&tags))
+}
+
+/// Returns a tag by its name or one of its synonyms.
+#[utoipa::path(
+    get,
+    path = "/tags/{name}",
+    tag = "tags",
+    params(("name" = String, Path, description = "Name or synonym of the tag")),
+    responses(
+        (status = 200, description = "The tag", body = Tag),
+        (status = 404, description = "No such tag", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn get_tag(name: String, store: Store) -> Result<impl warp::Reply, warp::Rejection> {
+    let name = tag_name(&name);
+    match store.get_tag(&name).await? {
+        Some(tag) => Ok(warp::reply::json(&tag)),
+        None => Err(warp::reject::custom(Error::NotFound(format!(
+            "tag {}",
+            name
+        )))),
+    }
+}
+
+/// Sets the description and wiki of a tag, only moderators may.
+#[utoipa::path(
+    put,
+    path = "/tags/{name}",
+    tag = "tags",
+    params(("name" = String, Path, description = "Name of the tag")),
+    request_body = TagEdit,
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The updated tag", body = Tag),
+        (status = 401, description = "Not logged in as a moderator", body = ErrorBody),
+        (status = 404, description = "No such tag", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn update_tag(
+    name: String,
+    session: Session,
+    store: Store,
+    edit: TagEdit,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    require_moderator(&session, &store).await?;
+    let name = tag_name(&name);
+    if !store.update_tag(&name, edit).await? {
+        return Err(warp::reject::custom(Error::NotFound(format!(
+            "tag {}",
+            name
+        ))));
+    }
+    match store.get_tag(&name).await? {
+        Some(tag) => Ok(warp::reply::json(&tag)),
+        None => Err(warp::reject::custom(Error::NotFound(format!(
+            "tag {}",
+            name
+        )))),
+    }
+}
+
+/// Makes a tag a synonym of another one and replaces it in every question that has it.
+///
+/// Names that aren't tags can be merged too, to add them as synonyms. Only moderators may.
+#[utoipa::path(
+    post,
+    path = "/tags/{name}/merge",
+    tag = "tags",
+    params(("name" = String, Path, description = "Name of the tag to merge")),
+    request_body = TagMerge,
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The tag merged into, with the merged name as a synonym", body = Tag),
+        (status = 401, description = "Not logged in as a moderator", body = ErrorBody),
+        (status = 404, description = "No tag to merge into", body = ErrorBody),
+        (status = 422, description = "Merging a tag into itself", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn merge_tag(
+    name: String,
+    session: Session,
+    store: Store,
+    merge: TagMerge,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    require_moderator(&session, &store).await?;
+    let source = tag_name(&name);
+    let into = tag::normalize(&merge.into);
+    let into = store
+        .get_tag(&into)
+        .await?
+        .ok_or_else(|| Error::NotFound(format!("tag {}", into)))?;
+    if source.is_empty() || source == into.name {
+        return Err(warp::reject::custom(Error::InvalidBody(format!(
+            "{} can't be merged into {}",
+            source, into.name
+        ))));
+    }
+
+    store
+        .merge_tag(&source, &into.name, &session.account_id)
+        .await?;
+    match store.get_tag(&into.name).await? {
+        Some(tag) => Ok(warp::reply::json(&tag)),
+        None => Err(warp::reject::custom(Error::NotFound(format!(
+            "tag {}",
+            into.name
+        )))),
+    }
+}
+
+async fn require_moderator(session: &Session, store: &Store) -> Result<(), Error> {
+    if session.is_login() && store.is_moderator(&session.account_id).await? {
+        Ok(())
+    } else {
+        Err(Error::Unauthorized)
+    }
+}
+
+/// Returns the normalized tag name in a path param, which arrives percent encoded as tags can
+/// contain e.g. `#` or `+`.
+pub fn tag_name(param: &str) -> String {
+    tag::normalize(&percent_encoding::percent_decode_str(param).decode_utf8_lossy())
+}
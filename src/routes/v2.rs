@@ -0,0 +1,364 @@
+use crate::etag;
+use crate::profanity::check_profanity;
+use crate::routes::answer::create_answer;
+use crate::routes::authentication::create_account;
+use crate::routes::question::{change_question, create_question};
+use crate::routes::tag::canonical_tags;
+use crate::store::Store;
+use crate::types::account::{Account, AccountInfo, Session};
+use crate::types::answer::{Answer, NewAnswer};
+use crate::types::api_key::Scope;
+use crate::types::question::{NewQuestion, Question, QuestionEdit, QuestionId, QuestionPatch};
+
+use handle_errors::{Error, ErrorBody};
+use serde::{de::DeserializeOwned, Serialize};
+use warp::http::StatusCode;
+use warp::hyper::body::Bytes;
+use warp::Filter;
+
+/// Replies 201 with resource and where it can be fetched from.
+fn created<T: Serialize>(location: String, resource: &T) -> impl warp::Reply {
+    warp::reply::with_header(
+        warp::reply::with_status(warp::reply::json(resource), StatusCode::CREATED),
+        "Location",
+        location,
+    )
+}
+
+/// Like `add_question` but replies with the added question.
+#[utoipa::path(
+    post,
+    path = "/questions",
+    tag = "questions",
+    request_body = NewQuestion,
+    security(("token" = []), ("api_key" = ["post_questions"])),
+    responses(
+        (status = 201, description = "The added question, with bad words censored", body = Question,
+            headers(
+                ("Location" = String, description = "Where to get the question"),
+                ("ETag" = String, description = "Version of the question"),
+            )),
+        (status = 401, description = "Not logged in, or the API key lacks the scope", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+        (status = 503, description = "Content filter unavailable", body = ErrorBody),
+    )
+)]
+pub async fn add_question(
+    session: Session,
+    store: Store,
+    new_question: NewQuestion,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let question = create_question(&session, &store, new_question).await?;
+    Ok(warp::reply::with_header(
+        created(format!("/v2/questions/{}", question.id.0), &question),
+        "ETag",
+        etag::of_version(question.version),
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/questions/{id}",
+    tag = "questions",
+    params(
+        ("id" = i32, Path, description = "Question id"),
+        ("If-None-Match" = Option<String>, Header, description = "ETag of a previous reply"),
+    ),
+    responses(
+        (status = 200, description = "The question", body = Question,
+            headers(("ETag" = String, description = "Version of the question"))),
+        (status = 304, description = "Not modified since the reply with If-None-Match"),
+        (status = 404, description = "No such question", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn get_question(
+    id: i32,
+    if_none_match: Option<String>,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match store.get_questions_by_ids(&[id]).await?.pop() {
+        Some((question, _)) => Ok(etag::reply(
+            warp::reply::json(&question),
+            &etag::of_version(question.version),
+            if_none_match,
+        )),
+        None => Err(warp::reject::custom(Error::NotFound(format!(
+            "question {}",
+            id
+        )))),
+    }
+}
+
+/// Like `update_question` but requires `If-Match`, so edits can't overwrite each other.
+///
+/// An edit summary can be sent along, it's kept in the revision of the edit.
+#[utoipa::path(
+    put,
+    path = "/questions/{id}",
+    tag = "questions",
+    params(
+        ("id" = i32, Path, description = "Question id"),
+        ("If-Match" = String, Header, description = "ETag the question was read with"),
+    ),
+    request_body = QuestionEdit,
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The updated question", body = Question,
+            headers(("ETag" = String, description = "Version of the updated question"))),
+        (status = 401, description = "Not logged in, or not the owner", body = ErrorBody),
+        (status = 412, description = "Changed since the If-Match version", body = ErrorBody),
+        (status = 428, description = "Missing If-Match", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+        (status = 503, description = "Content filter unavailable", body = ErrorBody),
+    )
+)]
+pub async fn update_question(
+    id: i32,
+    session: Session,
+    store: Store,
+    if_match: Option<String>,
+    edit: QuestionEdit,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let if_match = if_match.ok_or(Error::PreconditionRequired)?;
+    let question = change_question(
+        &session,
+        &store,
+        id,
+        edit.question,
+        etag::versions_in(&if_match),
+        edit.summary,
+    )
+    .await?;
+    Ok(warp::reply::with_header(
+        warp::reply::json(&question),
+        "ETag",
+        etag::of_version(question.version),
+    ))
+}
+
+/// Media type of RFC 7396 merge patches, plain JSON is accepted as well.
+const MERGE_PATCH: &str = "application/merge-patch+json";
+
+/// Reads a merge patch body, which `warp::body::json` rejects for its media type.
+pub fn merge_patch<T: DeserializeOwned + Send>(
+) -> impl Filter<Extract = (T,), Error = warp::Rejection> + Copy {
+    warp::header::optional::<String>("content-type")
+        .and(warp::body::bytes())
+        .and_then(|content_type: Option<String>, body: Bytes| async move {
+            let media_type = content_type
+                .as_deref()
+                .and_then(|c| c.split(';').next())
+                .map(|c| c.trim().to_lowercase());
+            if !matches!(
+                media_type.as_deref(),
+                Some(MERGE_PATCH | "application/json")
+            ) {
+                return Err(warp::reject::custom(Error::UnsupportedMediaType(
+                    MERGE_PATCH.to_string(),
+                )));
+            }
+            serde_json::from_slice(&body)
+                .map_err(|e| warp::reject::custom(Error::InvalidBody(e.to_string())))
+        })
+}
+
+/// Changes only the fields of a question in a merge patch.
+///
+/// Only text that changed goes through the content filter. Requires `If-Match` like
+/// `update_question`.
+#[utoipa::path(
+    patch,
+    path = "/questions/{id}",
+    tag = "questions",
+    params(
+        ("id" = i32, Path, description = "Question id"),
+        ("If-Match" = String, Header, description = "ETag the question was read with"),
+    ),
+    request_body(content = QuestionPatch, content_type = "application/merge-patch+json"),
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "The updated question", body = Question,
+            headers(("ETag" = String, description = "Version of the updated question"))),
+        (status = 401, description = "Not logged in, or not the owner", body = ErrorBody),
+        (status = 404, description = "No such question", body = ErrorBody),
+        (status = 412, description = "Changed since the If-Match version", body = ErrorBody),
+        (status = 415, description = "Not a merge patch", body = ErrorBody),
+        (status = 422, description = "Not a valid patch of a question", body = ErrorBody),
+        (status = 428, description = "Missing If-Match", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+        (status = 503, description = "Content filter unavailable", body = ErrorBody),
+    )
+)]
+pub async fn patch_question(
+    id: i32,
+    session: Session,
+    store: Store,
+    if_match: Option<String>,
+    patch: QuestionPatch,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let if_match = if_match.ok_or(Error::PreconditionRequired)?;
+    if !session.is_login() || !store.is_question_owner(id, &session.account_id).await? {
+        return Err(warp::reject::custom(Error::Unauthorized));
+    }
+    let (current, _) = store
+        .get_questions_by_ids(&[id])
+        .await?
+        .pop()
+        .ok_or_else(|| Error::NotFound(format!("question {}", id)))?;
+    if etag::versions_in(&if_match).is_some_and(|versions| !versions.contains(&current.version)) {
+        return Err(warp::reject::custom(Error::PreconditionFailed));
+    }
+
+    let censor = |new: Option<String>, old: String| async move {
+        match new {
+            Some(new) if new != old => check_profanity(new).await,
+            _ => Ok(old),
+        }
+    };
+    let (title, content) = tokio::join!(
+        censor(patch.title, current.title),
+        censor(patch.content, current.content)
+    );
+    let question = Question {
+        id: QuestionId(id),
+        title: title?,
+        content: content?,
+        tags: match patch.tags {
+            Some(tags) => canonical_tags(&store, tags).await?,
+            None => current.tags,
+        },
+        version: current.version,
+    };
+
+    // Conditional on the version the patch was applied to, in case it changed since.
+    let question = store
+        .update_question(
+            question,
+            id,
+            session.account_id.clone(),
+            Some(vec![current.version]),
+            patch.summary,
+        )
+        .await?
+        .ok_or(Error::PreconditionFailed)?;
+    Ok(warp::reply::with_header(
+        warp::reply::json(&question),
+        "ETag",
+        etag::of_version(question.version),
+    ))
+}
+
+/// Like `add_answer` but replies with the added answer.
+#[utoipa::path(
+    post,
+    path = "/answers",
+    tag = "answers",
+    request_body = NewAnswer,
+    security(("token" = []), ("api_key" = ["post_answers"])),
+    responses(
+        (status = 201, description = "The added answer, with bad words censored", body = Answer,
+            headers(
+                ("Location" = String, description = "Where to get the answer"),
+                ("ETag" = String, description = "Version of the answer"),
+            )),
+        (status = 401, description = "Not logged in, or the API key lacks the scope", body = ErrorBody),
+        (status = 404, description = "No such question", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+        (status = 503, description = "Content filter unavailable", body = ErrorBody),
+    )
+)]
+pub async fn add_answer(
+    session: Session,
+    store: Store,
+    new_answer: NewAnswer,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let answer = create_answer(&session, &store, new_answer).await?;
+    Ok(warp::reply::with_header(
+        created(format!("/v2/answers/{}", answer.id.0), &answer),
+        "ETag",
+        etag::of_version(answer.version),
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/answers/{id}",
+    tag = "answers",
+    params(
+        ("id" = i32, Path, description = "Answer id"),
+        ("If-None-Match" = Option<String>, Header, description = "ETag of a previous reply"),
+    ),
+    responses(
+        (status = 200, description = "The answer", body = Answer,
+            headers(("ETag" = String, description = "Version of the answer"))),
+        (status = 304, description = "Not modified since the reply with If-None-Match"),
+        (status = 404, description = "No such answer", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn get_answer(
+    id: i32,
+    if_none_match: Option<String>,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match store.get_answer(id).await? {
+        Some(answer) => Ok(etag::reply(
+            warp::reply::json(&answer),
+            &etag::of_version(answer.version),
+            if_none_match,
+        )),
+        None => Err(warp::reject::custom(Error::NotFound(format!(
+            "answer {}",
+            id
+        )))),
+    }
+}
+
+/// Like `register` but replies with the added account.
+#[utoipa::path(
+    post,
+    path = "/registration",
+    tag = "accounts",
+    request_body = Account,
+    responses(
+        (status = 201, description = "The added account", body = AccountInfo,
+            headers(("Location" = String, description = "Where to get the account"))),
+        (status = 422, description = "Password too weak, or email taken", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn register(store: Store, account: Account) -> Result<impl warp::Reply, warp::Rejection> {
+    let account = create_account(&store, account).await?;
+    Ok(created(format!("/v2/accounts/{}", account.id.0), &account))
+}
+
+/// Returns an account to its owner, other accounts can't be read.
+#[utoipa::path(
+    get,
+    path = "/accounts/{id}",
+    tag = "accounts",
+    params(("id" = i32, Path, description = "Account id")),
+    security(("token" = []), ("api_key" = ["read"])),
+    responses(
+        (status = 200, description = "The account", body = AccountInfo),
+        (status = 401, description = "Not logged in, not this account, or an API key without the read scope", body = ErrorBody),
+        (status = 429, description = "Rate limited", body = ErrorBody),
+    )
+)]
+pub async fn get_account(
+    id: i32,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if session.account_id.0 != id || !session.allows(Scope::Read) {
+        return Err(warp::reject::custom(Error::Unauthorized));
+    }
+    match store.get_account_emails(&[id]).await?.pop() {
+        Some((id, email)) => Ok(warp::reply::json(&AccountInfo { id, email })),
+        None => Err(warp::reject::custom(Error::NotFound(format!(
+            "account {}",
+            id
+        )))),
+    }
+}
@@ -0,0 +1,453 @@
+use std::convert::Infallible;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::os::unix::fs::FileTypeExt;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use handle_errors::REQUEST_ID;
+use hyper::server::accept::Accept;
+use hyper::service::{make_service_fn, service_fn, Service};
+use hyper::{Body, Request, Response};
+use opentelemetry::{global, propagation::Extractor};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, UnixListener};
+use tokio::signal::unix::SignalKind;
+use tokio::sync::{mpsc, watch};
+use tracing::{field, Instrument};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use warp::http::{header::LOCATION, uri::Authority, HeaderMap, HeaderValue, StatusCode};
+use warp::path::FullPath;
+use warp::{Filter, Rejection, Reply};
+
+use crate::rate_limit;
+use crate::tls;
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Address of the client, stored in the request extensions by `serve`.
+#[derive(Debug, Clone, Copy)]
+struct RemoteAddr(SocketAddr);
+
+/// Extracts the client's address, None for connections over a Unix socket.
+///
+/// Replaces `warp::addr::remote`, which doesn't work for routes served through `serve`.
+pub fn remote() -> impl Filter<Extract = (Option<SocketAddr>,), Error = Infallible> + Clone {
+    warp::ext::optional::<RemoteAddr>().map(|addr: Option<RemoteAddr>| addr.map(|a| a.0))
+}
+
+/// Lets the OpenTelemetry propagator read warp's headers.
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl<'a> Extractor for HeaderExtractor<'a> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+/// Returns the id of a request.
+///
+/// That's the client's `X-Request-Id` if it looks sane, otherwise the trace id of a W3C
+/// `traceparent`, otherwise a new random id.
+fn request_id(headers: &HeaderMap) -> String {
+    let valid = |id: &str| {
+        !id.is_empty()
+            && id.len() <= 128
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+    };
+    if let Some(id) = headers.get(REQUEST_ID_HEADER).and_then(|v| v.to_str().ok()) {
+        if valid(id) {
+            return id.to_string();
+        }
+    }
+    // traceparent is `<version>-<trace id>-<parent id>-<flags>`.
+    if let Some(trace_id) = headers
+        .get("traceparent")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split('-').nth(1))
+    {
+        if trace_id.len() == 32 && trace_id.chars().all(|c| c.is_ascii_hexdigit()) {
+            return trace_id.to_string();
+        }
+    }
+    uuid::Uuid::new_v4().to_string()
+}
+
+/// Handles a request with the warp service inside a span carrying the request id, and echoes the
+/// id back in the `X-Request-Id` response header along with the `X-RateLimit-*` headers of the
+/// limit the request counted against.
+async fn handle<S>(
+    mut svc: S,
+    remote: Option<SocketAddr>,
+    mut req: Request<Body>,
+) -> Result<Response<Body>, Infallible>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Infallible>,
+{
+    let id = request_id(req.headers());
+    let id_header = HeaderValue::from_str(&id).expect("request ids are valid header values");
+    // Let handlers read the id we settled on.
+    req.headers_mut()
+        .insert(REQUEST_ID_HEADER, id_header.clone());
+    if let Some(remote) = remote {
+        req.extensions_mut().insert(RemoteAddr(remote));
+    }
+
+    let span = tracing::info_span!(
+        "request",
+        method = %req.method(),
+        path = %req.uri().path(),
+        request_id = %id,
+        status = field::Empty,
+        otel.kind = "server",
+    );
+    let parent = global::get_text_map_propagator(|p| p.extract(&HeaderExtractor(req.headers())));
+    span.set_parent(parent);
+
+    let (res, quota) = rate_limit::with_quota(REQUEST_ID.scope(id, svc.call(req)))
+        .instrument(span.clone())
+        .await;
+    let mut res = res?;
+    span.record("status", res.status().as_u16());
+    let headers = res.headers_mut();
+    headers.insert(REQUEST_ID_HEADER, id_header);
+    if let Some(quota) = quota {
+        headers.insert("X-RateLimit-Limit", quota.limit.into());
+        headers.insert("X-RateLimit-Remaining", quota.remaining.into());
+        headers.insert("X-RateLimit-Reset", quota.reset.into());
+    }
+    Ok(res)
+}
+
+/// Resolves on SIGINT or SIGTERM.
+async fn signal() {
+    let terminate = async {
+        match tokio::signal::unix::signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "cannot listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await
+            }
+        }
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate => {}
+    }
+}
+
+/// Tells listeners when the process has been asked to stop.
+#[derive(Debug, Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    /// Starts listening for the shutdown signals.
+    pub fn listen() -> Self {
+        let (tx, rx) = watch::channel(false);
+        tokio::spawn(async move {
+            signal().await;
+            tracing::event!(tracing::Level::INFO, "shutdown requested");
+            let _ = tx.send(true);
+        });
+        Shutdown(rx)
+    }
+
+    /// Resolves once shutdown has been requested.
+    pub async fn wait(mut self) {
+        let _ = self.0.wait_for(|requested| *requested).await;
+    }
+}
+
+/// Where to accept connections.
+pub enum Listener {
+    Tcp(SocketAddr),
+    Tls(SocketAddr, tls::Acceptor),
+    /// Unix domain socket, e.g. for a reverse proxy on the same host.
+    Unix(PathBuf),
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Listener::Tcp(addr) => write!(f, "http://{}", addr),
+            Listener::Tls(addr, _) => write!(f, "https://{}", addr),
+            Listener::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Slow or stalled TLS handshakes are given up after this long.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+trait Io: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Io for T {}
+
+/// A client connection from any kind of listener.
+struct Conn {
+    io: Box<dyn Io>,
+    /// None for Unix sockets.
+    remote: Option<SocketAddr>,
+}
+
+impl AsyncRead for Conn {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Conn {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.io.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
+
+/// Hands connections from the accept loops to hyper.
+struct Incoming(mpsc::Receiver<Conn>);
+
+impl Accept for Incoming {
+    type Conn = Conn;
+    type Error = io::Error;
+
+    fn poll_accept(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Conn, io::Error>>> {
+        self.get_mut().0.poll_recv(cx).map(|conn| conn.map(Ok))
+    }
+}
+
+impl Listener {
+    /// Starts accepting connections, until shutdown.
+    async fn bind(&self, shutdown: Shutdown) -> io::Result<Incoming> {
+        let (tx, rx) = mpsc::channel(64);
+        match self {
+            Listener::Tcp(addr) => {
+                let listener = TcpListener::bind(addr).await?;
+                tokio::spawn(accept_tcp(listener, None, tx, shutdown));
+            }
+            Listener::Tls(addr, tls) => {
+                let listener = TcpListener::bind(addr).await?;
+                tokio::spawn(accept_tcp(listener, Some(tls.clone()), tx, shutdown));
+            }
+            Listener::Unix(path) => {
+                // A socket left behind by a previous run would make bind fail.
+                if std::fs::metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+                    std::fs::remove_file(path)?;
+                }
+                let listener = UnixListener::bind(path)?;
+                tokio::spawn(accept_unix(listener, path.clone(), tx, shutdown));
+            }
+        }
+        Ok(Incoming(rx))
+    }
+}
+
+/// Logs a failed accept and backs off a little, it's usually running out of file descriptors.
+async fn accept_failed(e: io::Error) {
+    tracing::event!(tracing::Level::ERROR, "cannot accept connection: {}", e);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+}
+
+async fn accept_tcp(
+    listener: TcpListener,
+    tls: Option<tls::Acceptor>,
+    tx: mpsc::Sender<Conn>,
+    shutdown: Shutdown,
+) {
+    loop {
+        let (stream, remote) = tokio::select! {
+            res = listener.accept() => match res {
+                Ok(conn) => conn,
+                Err(e) => {
+                    accept_failed(e).await;
+                    continue;
+                }
+            },
+            _ = shutdown.clone().wait() => return,
+        };
+        let _ = stream.set_nodelay(true);
+
+        let Some(tls) = &tls else {
+            let conn = Conn {
+                io: Box::new(stream),
+                remote: Some(remote),
+            };
+            if tx.send(conn).await.is_err() {
+                return;
+            }
+            continue;
+        };
+        // Handshakes run on their own so a slow client doesn't hold up the others.
+        let acceptor = tls.acceptor();
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => {
+                    let conn = Conn {
+                        io: Box::new(stream),
+                        remote: Some(remote),
+                    };
+                    let _ = tx.send(conn).await;
+                }
+                Ok(Err(e)) => {
+                    tracing::event!(tracing::Level::DEBUG, %remote, "TLS handshake failed: {}", e)
+                }
+                Err(_) => {
+                    tracing::event!(tracing::Level::DEBUG, %remote, "TLS handshake timed out")
+                }
+            }
+        });
+    }
+}
+
+async fn accept_unix(
+    listener: UnixListener,
+    path: PathBuf,
+    tx: mpsc::Sender<Conn>,
+    shutdown: Shutdown,
+) {
+    loop {
+        let stream = tokio::select! {
+            res = listener.accept() => match res {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    accept_failed(e).await;
+                    continue;
+                }
+            },
+            _ = shutdown.clone().wait() => break,
+        };
+        let conn = Conn {
+            io: Box::new(stream),
+            remote: None,
+        };
+        if tx.send(conn).await.is_err() {
+            break;
+        }
+    }
+    let _ = std::fs::remove_file(path);
+}
+
+/// Serves svc, usually made from routes with `warp::service`, on listener until shutdown.
+///
+/// Once shutdown is requested no new connections are accepted, and requests in flight get up to
+/// drain to finish before their connections are dropped. Fails if listener can't be bound.
+pub async fn serve<S>(
+    svc: S,
+    listener: Listener,
+    shutdown: Shutdown,
+    drain: Duration,
+) -> io::Result<()>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Infallible>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+{
+    let incoming = match listener.bind(shutdown.clone()).await {
+        Ok(incoming) => incoming,
+        Err(e) => {
+            return Err(io::Error::new(
+                e.kind(),
+                format!("cannot listen on {}: {}", listener, e),
+            ))
+        }
+    };
+    tracing::event!(tracing::Level::INFO, "listening on {}", listener);
+
+    let make_svc = make_service_fn(move |conn: &Conn| {
+        let svc = svc.clone();
+        let remote = conn.remote;
+        async move { Ok::<_, Infallible>(service_fn(move |req| handle(svc.clone(), remote, req))) }
+    });
+
+    let server = hyper::Server::builder(incoming)
+        .serve(make_svc)
+        .with_graceful_shutdown(shutdown.clone().wait());
+    tokio::pin!(server);
+
+    let res = tokio::select! {
+        res = &mut server => res,
+        _ = shutdown.wait() => match tokio::time::timeout(drain, &mut server).await {
+            Ok(res) => res,
+            Err(_) => {
+                tracing::event!(
+                    tracing::Level::WARN,
+                    "requests still running after {:?}, dropping them",
+                    drain
+                );
+                Ok(())
+            }
+        },
+    };
+    res.map_err(|e| io::Error::other(format!("server error on {}: {}", listener, e)))
+}
+
+/// Redirects every request to the same URL over HTTPS on port.
+pub fn redirect_to_https(
+    port: u16,
+) -> impl Filter<Extract = (warp::reply::Response,), Error = Rejection> + Clone {
+    warp::path::full()
+        .and(
+            warp::query::raw()
+                .map(|query: String| format!("?{}", query))
+                .or(warp::any().map(String::new))
+                .unify(),
+        )
+        .and(warp::header::optional::<Authority>("host"))
+        .map(
+            move |path: FullPath, query: String, host: Option<Authority>| {
+                let Some(host) = host else {
+                    return StatusCode::BAD_REQUEST.into_response();
+                };
+                let authority = match port {
+                    443 => host.host().to_string(),
+                    port => format!("{}:{}", host.host(), port),
+                };
+                let location = format!("https://{}{}{}", authority, path.as_str(), query);
+                warp::reply::with_header(StatusCode::PERMANENT_REDIRECT, LOCATION, location)
+                    .into_response()
+            },
+        )
+}
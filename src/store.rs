@@ -1,79 +1,142 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
 use handle_errors::Error;
+use tracing::instrument;
 
 use sqlx::{
-    postgres::{PgPoolOptions, PgRow},
+    migrate::Migrator,
+    postgres::{PgConnectOptions, PgPoolOptions, PgRow},
     PgPool, Row,
 };
 
+use crate::config::DatabaseConfig;
 use crate::types::{
-    account::{Account, AccountId},
+    account::{Account, AccountId, SessionId, SessionInfo},
     answer::{Answer, AnswerId, NewAnswer},
+    api_key::{ApiKey, ApiKeyId, NewApiKey, Scope},
+    follow::{FeedItem, Follows},
+    notification::{Notification, NotificationId, NotificationKind},
     question::{NewQuestion, Question, QuestionId},
+    revision::Revision,
+    tag::{Tag, TagEdit},
 };
 
+/// Migrations under `migrations/`, embedded at build time.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
 #[derive(Debug, Clone)]
 pub struct Store {
     pub connection: PgPool,
 }
 
 impl Store {
-    /// Connects to the postgres DB at the given url. Excpets a questions and answers table.
-    pub async fn new(db_url: &str) -> Self {
-        let db_pool = match PgPoolOptions::new()
-            .max_connections(5)
-            .connect(db_url)
+    /// Connects to the configured postgres DB. Excpets a questions and answers table.
+    pub async fn new(config: &DatabaseConfig) -> Result<Self, sqlx::Error> {
+        let mut options = PgConnectOptions::new()
+            .host(&config.host)
+            .port(config.port)
+            .username(&config.user)
+            .password(config.password.expose());
+        if let Some(name) = &config.name {
+            options = options.database(name);
+        }
+        let db_pool = PgPoolOptions::new()
+            .max_connections(config.max_connections)
+            .connect_with(options)
+            .await?;
+        Ok(Store {
+            connection: db_pool,
+        })
+    }
+
+    /// Checks a connection to the DB can be used.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn ping(&self) -> Option<Error> {
+        match sqlx::query("SELECT 1").execute(&self.connection).await {
+            Ok(_) => None,
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Some(Error::DatabaseQueryError("failed to ping DB".to_string()))
+            }
+        }
+    }
+
+    /// Returns versions of the embedded migrations that weren't applied to the DB yet.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn pending_migrations(&self) -> Result<Vec<i64>, Error> {
+        match sqlx::query("SELECT version FROM _sqlx_migrations WHERE success")
+            .map(|row: PgRow| row.get::<i64, _>("version"))
+            .fetch_all(&self.connection)
             .await
         {
-            Ok(pool) => pool,
-            Err(e) => panic!("Cloudn't establish DB connection: {}", e),
-        };
-        Store {
-            connection: db_pool,
+            Ok(applied) => Ok(MIGRATOR
+                .iter()
+                .filter(|m| m.migration_type.is_up_migration() && !applied.contains(&m.version))
+                .map(|m| m.version)
+                .collect()),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(
+                    "failed to query migrations".to_string(),
+                ))
+            }
         }
     }
 
     /// Returns Questions from DB.
     ///
     /// If limit is set we return |limit| questions starting from offset, otherwise return them
-    /// all.
+    /// all. They're ordered by id, so pages and the ETag of the list are stable.
+    #[instrument(level = "debug", skip_all)]
     pub async fn get_questions(
         &self,
         limit: Option<i32>,
         offset: i32,
     ) -> Result<Vec<Question>, Error> {
-        match sqlx::query("SELECT * FROM questions LIMIT $1 OFFSET $2")
-            .bind(limit)
-            .bind(offset)
-            .map(|row: PgRow| Question {
-                id: QuestionId(row.get("id")),
-                title: row.get("title"),
-                content: row.get("content"),
-                tags: row.get("tags"),
-            })
-            .fetch_all(&self.connection)
-            .await
+        match sqlx::query(
+            "SELECT * FROM questions WHERE deleted_at IS NULL ORDER BY id LIMIT $1 OFFSET $2",
+        )
+        .bind(limit)
+        .bind(offset)
+        .map(|row: PgRow| Question {
+            id: QuestionId(row.get("id")),
+            title: row.get("title"),
+            content: row.get("content"),
+            tags: row.get("tags"),
+            version: row.get("version"),
+        })
+        .fetch_all(&self.connection)
+        .await
         {
             Ok(questions) => Ok(questions),
             Err(e) => {
                 tracing::event!(tracing::Level::ERROR, "{:?}", e);
-                Err(Error::DatabaseQueryError(format!(
-                    "failed to query questions"
-                )))
+                Err(Error::DatabaseQueryError(
+                    "failed to query questions".to_string(),
+                ))
             }
         }
     }
 
     /// Adds the new question to the store.
     /// The added question is returned.
+    #[instrument(level = "debug", skip_all)]
     pub async fn add_question(
         &self,
         new_question: NewQuestion,
         account_id: AccountId,
     ) -> Result<Question, Error> {
         match sqlx::query(
-            "INSERT INTO questions (title, content,  tags, account_id)
-            VALUES ($1, $2, $3, $4) 
-            RETURNING id, title, content, tags
+            "WITH question AS (
+                INSERT INTO questions (title, content,  tags, account_id)
+                VALUES ($1, $2, $3, $4)
+                RETURNING id, title, content, tags, version, account_id
+            ), revision AS (
+                INSERT INTO question_revisions (question_id, version, title, content, tags, account_id)
+                SELECT id, version, title, content, tags, account_id FROM question
+            )
+            SELECT id, title, content, tags, version FROM question
             ",
         )
         .bind(new_question.title.clone())
@@ -85,6 +148,7 @@ impl Store {
             title: row.get("title"),
             content: row.get("content"),
             tags: row.get("tags"),
+            version: row.get("version"),
         })
         .fetch_one(&self.connection)
         .await
@@ -100,32 +164,47 @@ impl Store {
         }
     }
 
-    /// Updates question in store.
+    /// Updates question in store, keeping the update as a revision with summary.
     ///
-    /// Note that question.id is ignored and question_id is used.
+    /// Note that question.id is ignored and question_id is used. If versions is set the question
+    /// is only updated if its version is one of them, returns None if it wasn't updated.
+    #[instrument(level = "debug", skip_all)]
     pub async fn update_question(
         &self,
         question: Question,
         question_id: i32,
         account_id: AccountId,
-    ) -> Result<Question, Error> {
+        versions: Option<Vec<i32>>,
+        summary: Option<String>,
+    ) -> Result<Option<Question>, Error> {
         match sqlx::query(
-            "UPDATE questions SET title = $1, content = $2, tags = $3 
-            WHERE id = $4 and account_id = $5
-            RETURNING id, title, content, tags",
+            "WITH question AS (
+                UPDATE questions SET title = $1, content = $2, tags = $3, version = version + 1
+                WHERE id = $4 and account_id = $5 and deleted_at IS NULL
+                    and ($6::int[] IS NULL OR version = ANY($6))
+                RETURNING id, title, content, tags, version
+            ), revision AS (
+                INSERT INTO question_revisions
+                    (question_id, version, title, content, tags, account_id, summary)
+                SELECT id, version, title, content, tags, $5, $7 FROM question
+            )
+            SELECT * FROM question",
         )
         .bind(question.title)
         .bind(question.content)
         .bind(question.tags)
         .bind(question_id)
         .bind(account_id.0)
+        .bind(versions)
+        .bind(summary)
         .map(|row: PgRow| Question {
             id: QuestionId(row.get("id")),
             title: row.get("title"),
             content: row.get("content"),
             tags: row.get("tags"),
+            version: row.get("version"),
         })
-        .fetch_one(&self.connection)
+        .fetch_optional(&self.connection)
         .await
         {
             Ok(question) => Ok(question),
@@ -139,11 +218,114 @@ impl Store {
         }
     }
 
-    pub async fn delete_question(&self, question_id: i32) -> Option<Error> {
-        match sqlx::query("DELETE FROM questions WHERE id = $1")
-            .bind(question_id)
-            .execute(&self.connection)
-            .await
+    /// Sets question_id back to the title and content of its revision version and to tags, as a
+    /// new revision by editor. Tags are passed in as their names might have changed since.
+    ///
+    /// Like `update_question` it's only done if the question is at one of versions if they're
+    /// set. Returns None if it wasn't, or if there is no such revision.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn rollback_question(
+        &self,
+        question_id: i32,
+        version: i32,
+        tags: Option<Vec<String>>,
+        editor: &AccountId,
+        summary: String,
+        versions: Option<Vec<i32>>,
+    ) -> Result<Option<Question>, Error> {
+        match sqlx::query(
+            "WITH target AS (
+                SELECT title, content FROM question_revisions
+                WHERE question_id = $1 and version = $2
+            ), question AS (
+                UPDATE questions SET title = target.title, content = target.content,
+                    tags = $6, version = questions.version + 1
+                FROM target
+                WHERE questions.id = $1 and questions.deleted_at IS NULL
+                    and ($5::int[] IS NULL OR questions.version = ANY($5))
+                RETURNING questions.id, questions.title, questions.content, questions.tags,
+                    questions.version
+            ), revision AS (
+                INSERT INTO question_revisions
+                    (question_id, version, title, content, tags, account_id, summary)
+                SELECT id, version, title, content, tags, $3, $4 FROM question
+            )
+            SELECT * FROM question",
+        )
+        .bind(question_id)
+        .bind(version)
+        .bind(editor.0)
+        .bind(summary)
+        .bind(versions)
+        .bind(tags)
+        .map(|row: PgRow| Question {
+            id: QuestionId(row.get("id")),
+            title: row.get("title"),
+            content: row.get("content"),
+            tags: row.get("tags"),
+            version: row.get("version"),
+        })
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(question) => Ok(question),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(format!(
+                    "failed to roll back question {} ",
+                    question_id
+                )))
+            }
+        }
+    }
+
+    /// Returns the revisions of question_id oldest first, without diffs.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn get_question_revisions(&self, question_id: i32) -> Result<Vec<Revision>, Error> {
+        match sqlx::query(
+            "SELECT version, title, content, tags, account_id, summary, created_on
+            FROM question_revisions
+            WHERE question_id = $1
+                and question_id IN (SELECT id FROM questions WHERE deleted_at IS NULL)
+            ORDER BY version",
+        )
+        .bind(question_id)
+        .map(|row: PgRow| Revision {
+            version: row.get("version"),
+            title: row.get("title"),
+            content: row.get("content"),
+            tags: row.get("tags"),
+            editor: AccountId(row.get("account_id")),
+            summary: row.get("summary"),
+            created_on: row.get("created_on"),
+            diff: None,
+        })
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(revisions) => Ok(revisions),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(format!(
+                    "failed to get revisions of question {} ",
+                    question_id
+                )))
+            }
+        }
+    }
+
+    /// Marks question_id as deleted by account_id, hiding it and its answers until it's restored
+    /// or purged.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn delete_question(&self, question_id: i32, account_id: &AccountId) -> Option<Error> {
+        match sqlx::query(
+            "UPDATE questions SET deleted_at = NOW(), deleted_by = $2
+            WHERE id = $1 and deleted_at IS NULL",
+        )
+        .bind(question_id)
+        .bind(account_id.0)
+        .execute(&self.connection)
+        .await
         {
             Ok(_) => None,
             Err(e) => {
@@ -156,9 +338,123 @@ impl Store {
         }
     }
 
+    /// Undoes the deletion of question_id if it was deleted less than window ago. Unless
+    /// account_id is None it has to be the owner.
+    ///
+    /// Returns the restored question, or None if there was nothing to restore.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn restore_question(
+        &self,
+        question_id: i32,
+        account_id: Option<&AccountId>,
+        window: Duration,
+    ) -> Result<Option<Question>, Error> {
+        match sqlx::query(
+            "UPDATE questions SET deleted_at = NULL, deleted_by = NULL
+            WHERE id = $1 and ($2::int IS NULL OR account_id = $2)
+                and deleted_at > NOW() - make_interval(secs => $3)
+            RETURNING id, title, content, tags, version",
+        )
+        .bind(question_id)
+        .bind(account_id.map(|id| id.0))
+        .bind(window.as_secs_f64())
+        .map(|row: PgRow| Question {
+            id: QuestionId(row.get("id")),
+            title: row.get("title"),
+            content: row.get("content"),
+            tags: row.get("tags"),
+            version: row.get("version"),
+        })
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(question) => Ok(question),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(format!(
+                    "failed to restore question {}",
+                    question_id
+                )))
+            }
+        }
+    }
+
+    /// Returns questions with the accounts that asked them, like `get_questions`.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn get_questions_with_authors(
+        &self,
+        limit: Option<i32>,
+        offset: i32,
+    ) -> Result<Vec<(Question, AccountId)>, Error> {
+        match sqlx::query(
+            "SELECT * FROM questions WHERE deleted_at IS NULL ORDER BY id LIMIT $1 OFFSET $2",
+        )
+        .bind(limit)
+        .bind(offset)
+        .map(question_with_author)
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(questions) => Ok(questions),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(
+                    "failed to query questions".to_string(),
+                ))
+            }
+        }
+    }
+
+    /// Returns the questions with the given ids that exist, with the accounts that asked them.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn get_questions_by_ids(
+        &self,
+        question_ids: &[i32],
+    ) -> Result<Vec<(Question, AccountId)>, Error> {
+        match sqlx::query("SELECT * FROM questions WHERE id = ANY($1) and deleted_at IS NULL")
+            .bind(question_ids)
+            .map(question_with_author)
+            .fetch_all(&self.connection)
+            .await
+        {
+            Ok(questions) => Ok(questions),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(
+                    "failed to query questions by id".to_string(),
+                ))
+            }
+        }
+    }
+
+    /// Returns the questions asked by any of account_ids, oldest first.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn get_questions_by_account_ids(
+        &self,
+        account_ids: &[i32],
+    ) -> Result<Vec<(Question, AccountId)>, Error> {
+        match sqlx::query(
+            "SELECT * FROM questions WHERE account_id = ANY($1) and deleted_at IS NULL ORDER BY id",
+        )
+        .bind(account_ids)
+        .map(question_with_author)
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(questions) => Ok(questions),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(
+                    "failed to query questions by account".to_string(),
+                ))
+            }
+        }
+    }
+
     // ------ ------- Answer Resource --------
     /// Adds a new answer to the store.
     /// The added answer is returned.
+    #[instrument(level = "debug", skip_all)]
     pub async fn add_answer(
         &self,
         new_answer: NewAnswer,
@@ -166,8 +462,8 @@ impl Store {
     ) -> Result<Answer, Error> {
         match sqlx::query(
             "INSERT INTO answers (content, question_id, account_id)
-            VALUES ($1, $2, $3)
-            RETURNING id, content, question_id
+            SELECT $1, id, $3 FROM questions WHERE id = $2 and deleted_at IS NULL
+            RETURNING id, content, question_id, version
             ",
         )
         .bind(new_answer.content)
@@ -177,11 +473,17 @@ impl Store {
             id: AnswerId(row.get("id")),
             content: row.get("content"),
             question_id: QuestionId(row.get("question_id")),
+            version: row.get("version"),
         })
-        .fetch_one(&self.connection)
+        .fetch_optional(&self.connection)
         .await
         {
-            Ok(answer) => Ok(answer),
+            Ok(Some(answer)) => Ok(answer),
+            // Nothing is inserted if the question doesn't exist or was deleted.
+            Ok(None) => Err(Error::NotFound(format!(
+                "question {}",
+                new_answer.question_id.0
+            ))),
             Err(e) => {
                 tracing::event!(tracing::Level::ERROR, "{:?}", e);
                 Err(Error::DatabaseQueryError(format!(
@@ -192,21 +494,98 @@ impl Store {
         }
     }
 
-    // ------ ------- Account Resource --------
-    /// Adds a new account to the store.
-    ///
-    /// Not idempotent if an email exists an error is returned.
-    /// Password is salted
-    ///
-    /// Returns None on success and Some(err) if fail to add.
-    pub async fn add_account(&self, account: Account) -> Option<Error> {
+    /// Returns the answers to any of question_ids, oldest first, with their authors.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn get_answers_by_question_ids(
+        &self,
+        question_ids: &[i32],
+    ) -> Result<Vec<(Answer, AccountId)>, Error> {
         match sqlx::query(
-            "INSERT INTO accounts (email, password)
-            VALUES ($1, $2)
-            ",
+            "SELECT * FROM answers
+            WHERE question_id = ANY($1) and deleted_at IS NULL
+                and question_id IN (SELECT id FROM questions WHERE deleted_at IS NULL)
+            ORDER BY id",
         )
-        .bind(account.email.clone())
-        .bind(account.password)
+        .bind(question_ids)
+        .map(answer_with_author)
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(answers) => Ok(answers),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(
+                    "failed to query answers by question".to_string(),
+                ))
+            }
+        }
+    }
+
+    /// Returns the answers written by any of account_ids, oldest first.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn get_answers_by_account_ids(
+        &self,
+        account_ids: &[i32],
+    ) -> Result<Vec<(Answer, AccountId)>, Error> {
+        match sqlx::query(
+            "SELECT * FROM answers
+            WHERE account_id = ANY($1) and deleted_at IS NULL
+                and question_id IN (SELECT id FROM questions WHERE deleted_at IS NULL)
+            ORDER BY id",
+        )
+        .bind(account_ids)
+        .map(answer_with_author)
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(answers) => Ok(answers),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(
+                    "failed to query answers by account".to_string(),
+                ))
+            }
+        }
+    }
+
+    /// Returns the answer with the given id, if it exists.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn get_answer(&self, answer_id: i32) -> Result<Option<Answer>, Error> {
+        match sqlx::query(
+            "SELECT * FROM answers
+            WHERE id = $1 and deleted_at IS NULL
+                and question_id IN (SELECT id FROM questions WHERE deleted_at IS NULL)",
+        )
+        .bind(answer_id)
+        .map(|row: PgRow| Answer {
+            id: AnswerId(row.get("id")),
+            content: row.get("content"),
+            question_id: QuestionId(row.get("question_id")),
+            version: row.get("version"),
+        })
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(answer) => Ok(answer),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(format!(
+                    "failed to query answer {}",
+                    answer_id
+                )))
+            }
+        }
+    }
+
+    /// Marks answer_id as deleted by account_id, hiding it until it's restored or purged.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn delete_answer(&self, answer_id: i32, account_id: &AccountId) -> Option<Error> {
+        match sqlx::query(
+            "UPDATE answers SET deleted_at = NOW(), deleted_by = $2
+            WHERE id = $1 and deleted_at IS NULL",
+        )
+        .bind(answer_id)
+        .bind(account_id.0)
         .execute(&self.connection)
         .await
         {
@@ -214,54 +593,1456 @@ impl Store {
             Err(e) => {
                 tracing::event!(tracing::Level::ERROR, "{:?}", e);
                 Some(Error::DatabaseQueryError(format!(
-                    "Failed to add account for {} ",
-                    account.email
+                    "failed to delete answer {}",
+                    answer_id
                 )))
             }
         }
     }
 
-    /// Returns Account from DB.
-    pub async fn get_account(&self, email: String) -> Result<Account, Error> {
-        match sqlx::query("SELECT * FROM accounts WHERE email = $1")
-            .bind(email)
-            .map(|row: PgRow| Account {
-                id: Some(AccountId(row.get("id"))),
-                email: row.get("email"),
-                password: row.get("password"),
-            })
-            .fetch_one(&self.connection)
-            .await
+    /// Like `restore_question` for answer_id.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn restore_answer(
+        &self,
+        answer_id: i32,
+        account_id: Option<&AccountId>,
+        window: Duration,
+    ) -> Result<Option<Answer>, Error> {
+        match sqlx::query(
+            "UPDATE answers SET deleted_at = NULL, deleted_by = NULL
+            WHERE id = $1 and ($2::int IS NULL OR account_id = $2)
+                and deleted_at > NOW() - make_interval(secs => $3)
+            RETURNING id, content, question_id, version",
+        )
+        .bind(answer_id)
+        .bind(account_id.map(|id| id.0))
+        .bind(window.as_secs_f64())
+        .map(|row: PgRow| Answer {
+            id: AnswerId(row.get("id")),
+            content: row.get("content"),
+            question_id: QuestionId(row.get("question_id")),
+            version: row.get("version"),
+        })
+        .fetch_optional(&self.connection)
+        .await
         {
-            Ok(account) => Ok(account),
+            Ok(answer) => Ok(answer),
             Err(e) => {
                 tracing::event!(tracing::Level::ERROR, "{:?}", e);
                 Err(Error::DatabaseQueryError(format!(
-                    "failed to query accounts"
+                    "failed to restore answer {}",
+                    answer_id
                 )))
             }
         }
     }
 
-    /// Returns true if account_id created the given question_id.
-    pub async fn is_question_owner(
+    /// Returns true if account_id wrote the given answer_id.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn is_answer_owner(
         &self,
-        question_id: i32,
+        answer_id: i32,
         account_id: &AccountId,
     ) -> Result<bool, Error> {
-        match sqlx::query("SELECT * FROM questions WHERE id = $1 and account_id = $2")
-            .bind(question_id)
-            .bind(account_id.0)
-            .fetch_optional(&self.connection)
-            .await
+        match sqlx::query(
+            "SELECT id FROM answers WHERE id = $1 and account_id = $2 and deleted_at IS NULL",
+        )
+        .bind(answer_id)
+        .bind(account_id.0)
+        .fetch_optional(&self.connection)
+        .await
         {
-            Ok(question) => Ok(question.is_some()),
+            Ok(answer) => Ok(answer.is_some()),
             Err(e) => {
                 tracing::event!(tracing::Level::ERROR, "{:?}", e);
-                Err(Error::DatabaseQueryError(format!(
-                    "failed to query questions for acc owner"
-                )))
+                Err(Error::DatabaseQueryError(
+                    "failed to query answers for acc owner".to_string(),
+                ))
+            }
+        }
+    }
+
+    /// Deletes the questions and answers that were deleted more than retention ago for good,
+    /// along with the answers to those questions.
+    ///
+    /// Returns how many questions and answers were purged.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn purge_deleted(&self, retention: Duration) -> Result<(i64, i64), Error> {
+        match sqlx::query(
+            "WITH expired AS (
+                SELECT id FROM questions
+                WHERE deleted_at < NOW() - make_interval(secs => $1)
+            ), purged_answers AS (
+                DELETE FROM answers
+                WHERE deleted_at < NOW() - make_interval(secs => $1)
+                    OR question_id IN (SELECT id FROM expired)
+                RETURNING id
+            ), purged_questions AS (
+                DELETE FROM questions WHERE id IN (SELECT id FROM expired)
+                RETURNING id
+            )
+            SELECT (SELECT COUNT(*) FROM purged_questions) AS questions,
+                (SELECT COUNT(*) FROM purged_answers) AS answers",
+        )
+        .bind(retention.as_secs_f64())
+        .map(|row: PgRow| (row.get("questions"), row.get("answers")))
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(purged) => Ok(purged),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(
+                    "failed to purge deleted questions and answers".to_string(),
+                ))
             }
         }
     }
+
+    // ------ ------- Tag Resource --------
+    /// Replaces synonyms in names by their tags and adds the names that aren't tags yet.
+    ///
+    /// Names have to be normalized already, the result has no duplicates and keeps the order.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn canonical_tags(&self, names: Vec<String>) -> Result<Vec<String>, Error> {
+        match sqlx::query(
+            "WITH input AS (
+                SELECT name, ord FROM unnest($1::text[]) WITH ORDINALITY AS input(name, ord)
+            ), resolved AS (
+                SELECT COALESCE(tags.name, input.name) AS name, input.ord FROM input
+                LEFT JOIN tag_synonyms ON tag_synonyms.name = input.name
+                LEFT JOIN tags ON tags.id = tag_synonyms.tag_id
+            ), added AS (
+                INSERT INTO tags (name) SELECT DISTINCT name FROM resolved
+                ON CONFLICT (name) DO NOTHING
+            )
+            SELECT name FROM resolved GROUP BY name ORDER BY MIN(ord)",
+        )
+        .bind(names)
+        .map(|row: PgRow| row.get("name"))
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(names) => Ok(names),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(
+                    "failed to look up tags".to_string(),
+                ))
+            }
+        }
+    }
+
+    /// Returns up to limit tags, most used first. With prefix only those whose name or one of
+    /// its synonyms starts with it.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn get_tags(&self, prefix: Option<String>, limit: i64) -> Result<Vec<Tag>, Error> {
+        match sqlx::query(&format!(
+            "{} WHERE $1::text IS NULL OR starts_with(tags.name, $1)
+                OR EXISTS (
+                    SELECT 1 FROM tag_synonyms
+                    WHERE tag_synonyms.tag_id = tags.id and starts_with(tag_synonyms.name, $1)
+                )
+            ORDER BY questions DESC, tags.name LIMIT $2",
+            SELECT_TAGS
+        ))
+        .bind(prefix)
+        .bind(limit)
+        .map(tag_from_row)
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(tags) => Ok(tags),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(
+                    "failed to query tags".to_string(),
+                ))
+            }
+        }
+    }
+
+    /// Returns the tag called name, or the one name is a synonym of.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn get_tag(&self, name: &str) -> Result<Option<Tag>, Error> {
+        match sqlx::query(&format!(
+            "{} WHERE tags.name = $1
+                OR tags.id = (SELECT tag_id FROM tag_synonyms WHERE tag_synonyms.name = $1)",
+            SELECT_TAGS
+        ))
+        .bind(name)
+        .map(tag_from_row)
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(tag) => Ok(tag),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(format!(
+                    "failed to query tag {}",
+                    name
+                )))
+            }
+        }
+    }
+
+    /// Returns the tags of question_id, deleted or not, and none if it doesn't exist anymore.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn get_question_tags(&self, question_id: i32) -> Result<Vec<String>, Error> {
+        match sqlx::query("SELECT COALESCE(tags, '{}') AS tags FROM questions WHERE id = $1")
+            .bind(question_id)
+            .map(|row: PgRow| row.get("tags"))
+            .fetch_optional(&self.connection)
+            .await
+        {
+            Ok(tags) => Ok(tags.unwrap_or_default()),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(format!(
+                    "failed to query tags of question {}",
+                    question_id
+                )))
+            }
+        }
+    }
+
+    /// Sets the description and wiki of the tag called name, returns false if there is none.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn update_tag(&self, name: &str, edit: TagEdit) -> Result<bool, Error> {
+        match sqlx::query("UPDATE tags SET description = $2, wiki = $3 WHERE name = $1")
+            .bind(name)
+            .bind(edit.description)
+            .bind(edit.wiki)
+            .execute(&self.connection)
+            .await
+        {
+            Ok(result) => Ok(result.rows_affected() > 0),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(format!(
+                    "failed to update tag {}",
+                    name
+                )))
+            }
+        }
+    }
+
+    /// Makes source a synonym of the tag into, moving its synonyms and followers along and
+    /// replacing it in the questions that have it. Every changed question gets a revision by
+    /// editor.
+    ///
+    /// Into has to be a tag already. Returns how many questions were changed.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn merge_tag(
+        &self,
+        source: &str,
+        into: &str,
+        editor: &AccountId,
+    ) -> Result<u64, Error> {
+        match self.merge_tag_in_transaction(source, into, editor).await {
+            Ok(questions) => Ok(questions),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(format!(
+                    "failed to merge tag {} into {}",
+                    source, into
+                )))
+            }
+        }
+    }
+
+    async fn merge_tag_in_transaction(
+        &self,
+        source: &str,
+        into: &str,
+        editor: &AccountId,
+    ) -> Result<u64, sqlx::Error> {
+        let mut tx = self.connection.begin().await?;
+        let into_id: i32 = sqlx::query("SELECT id FROM tags WHERE name = $1")
+            .bind(into)
+            .map(|row: PgRow| row.get("id"))
+            .fetch_one(&mut *tx)
+            .await?;
+        sqlx::query(
+            "UPDATE tag_synonyms SET tag_id = $2
+            WHERE tag_id = (SELECT id FROM tags WHERE name = $1)",
+        )
+        .bind(source)
+        .bind(into_id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(
+            "INSERT INTO tag_follows (account_id, tag_id, created_on)
+            SELECT account_id, $2, created_on FROM tag_follows
+            WHERE tag_id = (SELECT id FROM tags WHERE name = $1)
+            ON CONFLICT DO NOTHING",
+        )
+        .bind(source)
+        .bind(into_id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM tags WHERE name = $1")
+            .bind(source)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "INSERT INTO tag_synonyms (name, tag_id) VALUES ($1, $2)
+            ON CONFLICT (name) DO UPDATE SET tag_id = EXCLUDED.tag_id",
+        )
+        .bind(source)
+        .bind(into_id)
+        .execute(&mut *tx)
+        .await?;
+        let questions = sqlx::query(
+            "WITH question AS (
+                UPDATE questions SET version = version + 1, tags = (
+                    SELECT array_agg(name ORDER BY ord) FROM (
+                        SELECT name, MIN(ord) AS ord
+                        FROM unnest(array_replace(tags, $1, $2)) WITH ORDINALITY AS t(name, ord)
+                        GROUP BY name
+                    ) merged
+                )
+                WHERE tags @> ARRAY[$1]
+                RETURNING id, title, content, tags, version
+            )
+            INSERT INTO question_revisions
+                (question_id, version, title, content, tags, account_id, summary)
+            SELECT id, version, title, content, tags, $3, $4 FROM question",
+        )
+        .bind(source)
+        .bind(into)
+        .bind(editor.0)
+        .bind(format!("Merged tag {} into {}", source, into))
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        tx.commit().await?;
+        Ok(questions)
+    }
+
+    // ------ ------- Follows --------
+    /// Makes account_id follow the tag called name, which has to exist.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn follow_tag(&self, account_id: &AccountId, name: &str) -> Option<Error> {
+        match sqlx::query(
+            "INSERT INTO tag_follows (account_id, tag_id)
+            SELECT $1, id FROM tags WHERE name = $2
+            ON CONFLICT DO NOTHING",
+        )
+        .bind(account_id.0)
+        .bind(name)
+        .execute(&self.connection)
+        .await
+        {
+            Ok(_) => None,
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Some(Error::DatabaseQueryError(format!(
+                    "failed to follow tag {}",
+                    name
+                )))
+            }
+        }
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    pub async fn unfollow_tag(&self, account_id: &AccountId, name: &str) -> Option<Error> {
+        match sqlx::query(
+            "DELETE FROM tag_follows
+            WHERE account_id = $1 and tag_id = (SELECT id FROM tags WHERE name = $2)",
+        )
+        .bind(account_id.0)
+        .bind(name)
+        .execute(&self.connection)
+        .await
+        {
+            Ok(_) => None,
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Some(Error::DatabaseQueryError(format!(
+                    "failed to unfollow tag {}",
+                    name
+                )))
+            }
+        }
+    }
+
+    /// Makes account_id follow question_id, which has to exist.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn follow_question(&self, account_id: &AccountId, question_id: i32) -> Option<Error> {
+        match sqlx::query(
+            "INSERT INTO question_follows (account_id, question_id) VALUES ($1, $2)
+            ON CONFLICT DO NOTHING",
+        )
+        .bind(account_id.0)
+        .bind(question_id)
+        .execute(&self.connection)
+        .await
+        {
+            Ok(_) => None,
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Some(Error::DatabaseQueryError(format!(
+                    "failed to follow question {}",
+                    question_id
+                )))
+            }
+        }
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    pub async fn unfollow_question(
+        &self,
+        account_id: &AccountId,
+        question_id: i32,
+    ) -> Option<Error> {
+        match sqlx::query("DELETE FROM question_follows WHERE account_id = $1 and question_id = $2")
+            .bind(account_id.0)
+            .bind(question_id)
+            .execute(&self.connection)
+            .await
+        {
+            Ok(_) => None,
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Some(Error::DatabaseQueryError(format!(
+                    "failed to unfollow question {}",
+                    question_id
+                )))
+            }
+        }
+    }
+
+    /// Returns the tags and questions account_id follows, oldest follow first.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn get_follows(&self, account_id: &AccountId) -> Result<Follows, Error> {
+        match sqlx::query(
+            "SELECT
+                ARRAY(
+                    SELECT tags.name FROM tag_follows JOIN tags ON tags.id = tag_follows.tag_id
+                    WHERE tag_follows.account_id = $1 ORDER BY tag_follows.created_on
+                ) AS tags,
+                ARRAY(
+                    SELECT question_id FROM question_follows
+                    WHERE account_id = $1 ORDER BY created_on
+                ) AS questions",
+        )
+        .bind(account_id.0)
+        .map(|row: PgRow| Follows {
+            tags: row.get("tags"),
+            questions: row
+                .get::<Vec<i32>, _>("questions")
+                .into_iter()
+                .map(QuestionId)
+                .collect(),
+        })
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(follows) => Ok(follows),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(format!(
+                    "failed to query follows of account {}",
+                    account_id.0
+                )))
+            }
+        }
+    }
+
+    /// Returns the questions in tags account_id follows and the answers to questions it follows,
+    /// newest first, leaving out its own.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn get_feed(
+        &self,
+        account_id: &AccountId,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<FeedItem>, Error> {
+        match sqlx::query(
+            "SELECT 'question' AS kind, id, title, content, tags, version,
+                NULL::int AS question_id, account_id, created_on::timestamptz AS at
+            FROM questions
+            WHERE deleted_at IS NULL and account_id <> $1 and tags && ARRAY(
+                SELECT tags.name FROM tag_follows JOIN tags ON tags.id = tag_follows.tag_id
+                WHERE tag_follows.account_id = $1
+            )
+            UNION ALL
+            SELECT 'answer', id, NULL, content, NULL, version,
+                question_id, account_id, created_on::timestamptz
+            FROM answers
+            WHERE deleted_at IS NULL and account_id <> $1 and question_id IN (
+                SELECT question_id FROM question_follows
+                JOIN questions ON questions.id = question_follows.question_id
+                WHERE question_follows.account_id = $1 and questions.deleted_at IS NULL
+            )
+            ORDER BY at DESC, kind, id DESC
+            LIMIT $2 OFFSET $3",
+        )
+        .bind(account_id.0)
+        .bind(limit)
+        .bind(offset)
+        .map(feed_item_from_row)
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(feed) => Ok(feed),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(format!(
+                    "failed to query feed of account {}",
+                    account_id.0
+                )))
+            }
+        }
+    }
+
+    // ------ ------- Notifications --------
+    /// Notifies the owner of question_id about answer_id if it's set, and the accounts with the
+    /// mentioned emails, leaving out actor.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn add_notifications(
+        &self,
+        question_id: i32,
+        answer_id: Option<i32>,
+        actor: &AccountId,
+        mentions: Vec<String>,
+    ) -> Option<Error> {
+        match sqlx::query(
+            "WITH answered AS (
+                SELECT account_id FROM questions
+                WHERE id = $1 and $2::int IS NOT NULL and account_id <> $3
+            ), mentioned AS (
+                SELECT id AS account_id FROM accounts
+                WHERE lower(email) = ANY($4) and id <> $3
+                    and id NOT IN (SELECT account_id FROM answered)
+            )
+            INSERT INTO notifications (account_id, kind, question_id, answer_id, actor_id)
+            SELECT account_id, $5, $1, $2, $3 FROM answered
+            UNION ALL
+            SELECT account_id, $6, $1, $2, $3 FROM mentioned",
+        )
+        .bind(question_id)
+        .bind(answer_id)
+        .bind(actor.0)
+        .bind(mentions)
+        .bind(NotificationKind::Answer.as_str())
+        .bind(NotificationKind::Mention.as_str())
+        .execute(&self.connection)
+        .await
+        {
+            Ok(_) => None,
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Some(Error::DatabaseQueryError(format!(
+                    "failed to add notifications for question {}",
+                    question_id
+                )))
+            }
+        }
+    }
+
+    /// Returns the notifications of account_id newest first, optionally only unread ones. Those
+    /// about deleted questions and answers are left out.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn get_notifications(
+        &self,
+        account_id: &AccountId,
+        unread: bool,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Notification>, Error> {
+        match sqlx::query(&format!(
+            "SELECT * FROM notifications WHERE account_id = $1 and {}
+                and (NOT $2 OR read_at IS NULL)
+            ORDER BY id DESC LIMIT $3 OFFSET $4",
+            NOTIFICATION_VISIBLE
+        ))
+        .bind(account_id.0)
+        .bind(unread)
+        .bind(limit)
+        .bind(offset)
+        .map(notification_from_row)
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(notifications) => Ok(notifications.into_iter().flatten().collect()),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(format!(
+                    "failed to query notifications of account {}",
+                    account_id.0
+                )))
+            }
+        }
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    pub async fn get_unread_count(&self, account_id: &AccountId) -> Result<i64, Error> {
+        match sqlx::query(&format!(
+            "SELECT COUNT(*) AS unread FROM notifications
+            WHERE account_id = $1 and read_at IS NULL and {}",
+            NOTIFICATION_VISIBLE
+        ))
+        .bind(account_id.0)
+        .map(|row: PgRow| row.get("unread"))
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(unread) => Ok(unread),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(format!(
+                    "failed to count notifications of account {}",
+                    account_id.0
+                )))
+            }
+        }
+    }
+
+    /// Marks notification_id of account_id as read, returns it or None if there's no such
+    /// notification.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn mark_notification_read(
+        &self,
+        notification_id: i32,
+        account_id: &AccountId,
+    ) -> Result<Option<Notification>, Error> {
+        match sqlx::query(
+            "UPDATE notifications SET read_at = COALESCE(read_at, NOW())
+            WHERE id = $1 and account_id = $2
+            RETURNING *",
+        )
+        .bind(notification_id)
+        .bind(account_id.0)
+        .map(notification_from_row)
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(notification) => Ok(notification.flatten()),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(format!(
+                    "failed to mark notification {} read",
+                    notification_id
+                )))
+            }
+        }
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    pub async fn mark_all_notifications_read(&self, account_id: &AccountId) -> Option<Error> {
+        match sqlx::query(
+            "UPDATE notifications SET read_at = NOW() WHERE account_id = $1 and read_at IS NULL",
+        )
+        .bind(account_id.0)
+        .execute(&self.connection)
+        .await
+        {
+            Ok(_) => None,
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Some(Error::DatabaseQueryError(format!(
+                    "failed to mark notifications of account {} read",
+                    account_id.0
+                )))
+            }
+        }
+    }
+
+    // ------ ------- Account Resource --------
+    /// Adds a new account to the store.
+    ///
+    /// Not idempotent if an email exists an error is returned.
+    /// Password is salted
+    ///
+    /// Returns the id of the added account.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn add_account(&self, account: Account) -> Result<AccountId, Error> {
+        match sqlx::query(
+            "INSERT INTO accounts (email, password)
+            VALUES ($1, $2)
+            RETURNING id
+            ",
+        )
+        .bind(account.email.clone())
+        .bind(account.password)
+        .map(|row: PgRow| AccountId(row.get("id")))
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(account_id) => Ok(account_id),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(format!(
+                    "Failed to add account for {} ",
+                    account.email
+                )))
+            }
+        }
+    }
+
+    /// Returns Account from DB, or None if no account is registered for email.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn get_account(&self, email: String) -> Result<Option<Account>, Error> {
+        match sqlx::query("SELECT * FROM accounts WHERE email = $1")
+            .bind(email)
+            .map(|row: PgRow| Account {
+                id: Some(AccountId(row.get("id"))),
+                email: row.get("email"),
+                password: row.get("password"),
+            })
+            .fetch_optional(&self.connection)
+            .await
+        {
+            Ok(account) => Ok(account),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(
+                    "failed to query accounts".to_string(),
+                ))
+            }
+        }
+    }
+
+    /// Returns the emails of the accounts with the given ids that exist.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn get_account_emails(
+        &self,
+        account_ids: &[i32],
+    ) -> Result<Vec<(AccountId, String)>, Error> {
+        match sqlx::query("SELECT id, email FROM accounts WHERE id = ANY($1)")
+            .bind(account_ids)
+            .map(|row: PgRow| (AccountId(row.get("id")), row.get("email")))
+            .fetch_all(&self.connection)
+            .await
+        {
+            Ok(accounts) => Ok(accounts),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(
+                    "failed to query accounts by id".to_string(),
+                ))
+            }
+        }
+    }
+
+    /// Replaces the password hash of account_id.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn update_account_password(
+        &self,
+        account_id: &AccountId,
+        password: String,
+    ) -> Option<Error> {
+        match sqlx::query("UPDATE accounts SET password = $1 WHERE id = $2")
+            .bind(password)
+            .bind(account_id.0)
+            .execute(&self.connection)
+            .await
+        {
+            Ok(_) => None,
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Some(Error::DatabaseQueryError(format!(
+                    "failed to update password of account {}",
+                    account_id.0
+                )))
+            }
+        }
+    }
+
+    /// Returns true if account_id created the given question_id.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn is_question_owner(
+        &self,
+        question_id: i32,
+        account_id: &AccountId,
+    ) -> Result<bool, Error> {
+        match sqlx::query(
+            "SELECT * FROM questions WHERE id = $1 and account_id = $2 and deleted_at IS NULL",
+        )
+        .bind(question_id)
+        .bind(account_id.0)
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(question) => Ok(question.is_some()),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(
+                    "failed to query questions for acc owner".to_string(),
+                ))
+            }
+        }
+    }
+
+    /// Returns true if account_id may moderate content it doesn't own.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn is_moderator(&self, account_id: &AccountId) -> Result<bool, Error> {
+        match sqlx::query("SELECT is_moderator FROM accounts WHERE id = $1")
+            .bind(account_id.0)
+            .map(|row: PgRow| row.get("is_moderator"))
+            .fetch_optional(&self.connection)
+            .await
+        {
+            Ok(is_moderator) => Ok(is_moderator.unwrap_or(false)),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(
+                    "failed to query accounts for moderators".to_string(),
+                ))
+            }
+        }
+    }
+
+    // ------ ------- Login throttling --------
+    /// Returns how many seconds are left on the longest active lockout among keys,
+    /// or None if none of them is locked.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn login_lockout_remaining(&self, keys: &[String]) -> Result<Option<i64>, Error> {
+        match sqlx::query(
+            "SELECT CEIL(EXTRACT(EPOCH FROM MAX(locked_until) - NOW()))::BIGINT AS remaining
+            FROM login_attempts
+            WHERE key = ANY($1) AND locked_until > NOW()",
+        )
+        .bind(keys)
+        .map(|row: PgRow| row.get::<Option<i64>, _>("remaining"))
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(remaining) => Ok(remaining),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(
+                    "failed to query login attempts".to_string(),
+                ))
+            }
+        }
+    }
+
+    /// Records a failed login for key.
+    ///
+    /// Failures older than window_secs are forgotten. Returns the number of failures in the
+    /// current window, including this one.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn record_login_failure(&self, key: &str, window_secs: i64) -> Result<i32, Error> {
+        match sqlx::query(
+            "INSERT INTO login_attempts (key, failures, last_failure)
+            VALUES ($1, 1, NOW())
+            ON CONFLICT (key) DO UPDATE SET
+                failures = CASE
+                    WHEN login_attempts.last_failure < NOW() - make_interval(secs => $2)
+                    THEN 1
+                    ELSE login_attempts.failures + 1
+                END,
+                last_failure = NOW()
+            RETURNING failures",
+        )
+        .bind(key)
+        .bind(window_secs as f64)
+        .map(|row: PgRow| row.get("failures"))
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(failures) => Ok(failures),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(
+                    "failed to record login attempt".to_string(),
+                ))
+            }
+        }
+    }
+
+    /// Locks key out of logging in for the next secs seconds and records a lockout event.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn lock_login(&self, key: &str, secs: i64) -> Option<Error> {
+        match sqlx::query(
+            "WITH locked AS (
+                UPDATE login_attempts SET locked_until = NOW() + make_interval(secs => $2)
+                WHERE key = $1
+                RETURNING key, failures, locked_until
+            )
+            INSERT INTO lockout_events (key, failures, locked_until)
+            SELECT key, failures, locked_until FROM locked",
+        )
+        .bind(key)
+        .bind(secs as f64)
+        .execute(&self.connection)
+        .await
+        {
+            Ok(_) => None,
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Some(Error::DatabaseQueryError(format!(
+                    "failed to lock login for {}",
+                    key
+                )))
+            }
+        }
+    }
+
+    /// Forgets all failed logins recorded for key.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn clear_login_failures(&self, key: &str) -> Option<Error> {
+        match sqlx::query("DELETE FROM login_attempts WHERE key = $1")
+            .bind(key)
+            .execute(&self.connection)
+            .await
+        {
+            Ok(_) => None,
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Some(Error::DatabaseQueryError(format!(
+                    "failed to clear login attempts for {}",
+                    key
+                )))
+            }
+        }
+    }
+
+    // ------ ------- Rate Limit Resource --------
+    /// Takes a token from the bucket for key, which holds up to burst tokens and gains
+    /// per_sec tokens a second. A new bucket starts out full.
+    ///
+    /// Returns the tokens left and whether one could be taken.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn take_rate_limit_token(
+        &self,
+        key: &str,
+        burst: f64,
+        per_sec: f64,
+    ) -> Result<(f64, bool), Error> {
+        match sqlx::query(
+            "INSERT INTO rate_limit_buckets AS b (key, tokens, last_allowed, updated_on)
+            VALUES ($1, $2 - 1, TRUE, NOW())
+            ON CONFLICT (key) DO UPDATE SET
+                tokens = CASE
+                    WHEN LEAST($2, b.tokens + EXTRACT(EPOCH FROM NOW() - b.updated_on) * $3) >= 1
+                    THEN LEAST($2, b.tokens + EXTRACT(EPOCH FROM NOW() - b.updated_on) * $3) - 1
+                    ELSE LEAST($2, b.tokens + EXTRACT(EPOCH FROM NOW() - b.updated_on) * $3)
+                END,
+                last_allowed =
+                    LEAST($2, b.tokens + EXTRACT(EPOCH FROM NOW() - b.updated_on) * $3) >= 1,
+                updated_on = NOW()
+            RETURNING tokens, last_allowed",
+        )
+        .bind(key)
+        .bind(burst)
+        .bind(per_sec)
+        .map(|row: PgRow| (row.get("tokens"), row.get("last_allowed")))
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(bucket) => Ok(bucket),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(
+                    "failed to take rate limit token".to_string(),
+                ))
+            }
+        }
+    }
+
+    /// Deletes buckets untouched for idle_secs, they'd be full again by now.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn delete_idle_rate_limits(&self, idle_secs: i64) -> Option<Error> {
+        match sqlx::query(
+            "DELETE FROM rate_limit_buckets WHERE updated_on < NOW() - make_interval(secs => $1)",
+        )
+        .bind(idle_secs as f64)
+        .execute(&self.connection)
+        .await
+        {
+            Ok(_) => None,
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Some(Error::DatabaseQueryError(
+                    "failed to delete idle rate limits".to_string(),
+                ))
+            }
+        }
+    }
+
+    // ------ ------- API Key Resource --------
+    /// Adds a new API key for account_id, only the hash of the key is stored.
+    /// The added key is returned.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn add_api_key(
+        &self,
+        new_key: NewApiKey,
+        prefix: String,
+        key_hash: String,
+        account_id: &AccountId,
+    ) -> Result<ApiKey, Error> {
+        let scopes: Vec<&str> = new_key.scopes.iter().map(|s| s.as_str()).collect();
+        match sqlx::query(
+            "INSERT INTO api_keys (account_id, name, prefix, key_hash, scopes, expires_on)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING *",
+        )
+        .bind(account_id.0)
+        .bind(new_key.name)
+        .bind(prefix)
+        .bind(key_hash)
+        .bind(scopes)
+        .bind(new_key.expires_on)
+        .map(|row: PgRow| api_key_from_row(&row))
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(key) => Ok(key),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(format!(
+                    "failed to add api key for account {}",
+                    account_id.0
+                )))
+            }
+        }
+    }
+
+    /// Returns the API keys of account_id.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn get_api_keys(&self, account_id: &AccountId) -> Result<Vec<ApiKey>, Error> {
+        match sqlx::query("SELECT * FROM api_keys WHERE account_id = $1 ORDER BY id")
+            .bind(account_id.0)
+            .map(|row: PgRow| api_key_from_row(&row))
+            .fetch_all(&self.connection)
+            .await
+        {
+            Ok(keys) => Ok(keys),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(
+                    "failed to query api keys".to_string(),
+                ))
+            }
+        }
+    }
+
+    /// Deletes API key key_id if it belongs to account_id.
+    ///
+    /// Returns false if there was no such key.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn delete_api_key(&self, key_id: i32, account_id: &AccountId) -> Result<bool, Error> {
+        match sqlx::query("DELETE FROM api_keys WHERE id = $1 AND account_id = $2")
+            .bind(key_id)
+            .bind(account_id.0)
+            .execute(&self.connection)
+            .await
+        {
+            Ok(res) => Ok(res.rows_affected() > 0),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(format!(
+                    "failed to delete api key {}",
+                    key_id
+                )))
+            }
+        }
+    }
+
+    /// Returns true if API key key_id of account_id still exists and hasn't expired.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn is_api_key_active(
+        &self,
+        key_id: &ApiKeyId,
+        account_id: &AccountId,
+    ) -> Result<bool, Error> {
+        match sqlx::query(
+            "SELECT id FROM api_keys
+            WHERE id = $1 AND account_id = $2 AND (expires_on IS NULL OR expires_on > NOW())",
+        )
+        .bind(key_id.0)
+        .bind(account_id.0)
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(key) => Ok(key.is_some()),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(
+                    "failed to query api keys".to_string(),
+                ))
+            }
+        }
+    }
+
+    /// Looks up an unexpired API key by the hash of the key and marks it as used.
+    ///
+    /// Returns the key together with the account it belongs to.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn use_api_key(&self, key_hash: &str) -> Result<Option<(AccountId, ApiKey)>, Error> {
+        match sqlx::query(
+            "UPDATE api_keys SET last_used = NOW()
+            WHERE key_hash = $1 AND (expires_on IS NULL OR expires_on > NOW())
+            RETURNING *",
+        )
+        .bind(key_hash)
+        .map(|row: PgRow| (AccountId(row.get("account_id")), api_key_from_row(&row)))
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(key) => Ok(key),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(
+                    "failed to query api keys".to_string(),
+                ))
+            }
+        }
+    }
+
+    // ------ ------- SSO --------
+    /// Remembers the PKCE verifier of an SSO login until the provider redirects back.
+    ///
+    /// Logins that were never completed are cleaned up on the way.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn add_oidc_login(&self, state: &str, code_verifier: &str) -> Option<Error> {
+        match sqlx::query(
+            "WITH expired AS (
+                DELETE FROM oidc_logins WHERE created_on < NOW() - INTERVAL '10 minutes'
+            )
+            INSERT INTO oidc_logins (state, code_verifier) VALUES ($1, $2)",
+        )
+        .bind(state)
+        .bind(code_verifier)
+        .execute(&self.connection)
+        .await
+        {
+            Ok(_) => None,
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Some(Error::DatabaseQueryError(
+                    "failed to add oidc login".to_string(),
+                ))
+            }
+        }
+    }
+
+    /// Removes the SSO login for state and returns its PKCE verifier.
+    ///
+    /// Returns None if there is no such login or it is older than 10 minutes.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn take_oidc_login(&self, state: &str) -> Result<Option<String>, Error> {
+        match sqlx::query(
+            "DELETE FROM oidc_logins
+            WHERE state = $1 AND created_on > NOW() - INTERVAL '10 minutes'
+            RETURNING code_verifier",
+        )
+        .bind(state)
+        .map(|row: PgRow| row.get("code_verifier"))
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(code_verifier) => Ok(code_verifier),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(
+                    "failed to query oidc logins".to_string(),
+                ))
+            }
+        }
+    }
+
+    /// Returns the account an external identity is linked to.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn get_identity_account(
+        &self,
+        issuer: &str,
+        subject: &str,
+    ) -> Result<Option<AccountId>, Error> {
+        match sqlx::query(
+            "SELECT account_id FROM account_identities WHERE issuer = $1 AND subject = $2",
+        )
+        .bind(issuer)
+        .bind(subject)
+        .map(|row: PgRow| AccountId(row.get("account_id")))
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(account_id) => Ok(account_id),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(
+                    "failed to query account identities".to_string(),
+                ))
+            }
+        }
+    }
+
+    /// Links an external identity to account_id.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn add_identity(
+        &self,
+        issuer: &str,
+        subject: &str,
+        account_id: &AccountId,
+    ) -> Option<Error> {
+        match sqlx::query(
+            "INSERT INTO account_identities (issuer, subject, account_id) VALUES ($1, $2, $3)",
+        )
+        .bind(issuer)
+        .bind(subject)
+        .bind(account_id.0)
+        .execute(&self.connection)
+        .await
+        {
+            Ok(_) => None,
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Some(Error::DatabaseQueryError(format!(
+                    "failed to link identity to account {}",
+                    account_id.0
+                )))
+            }
+        }
+    }
+
+    // ------ ------- Session Resource --------
+    /// Records a new login session, returning its id.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn add_session(
+        &self,
+        account_id: &AccountId,
+        user_agent: Option<String>,
+        ip: Option<String>,
+        expires_on: DateTime<Utc>,
+    ) -> Result<SessionId, Error> {
+        match sqlx::query(
+            "INSERT INTO sessions (account_id, user_agent, ip, expires_on)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id",
+        )
+        .bind(account_id.0)
+        .bind(user_agent)
+        .bind(ip)
+        .bind(expires_on)
+        .map(|row: PgRow| SessionId(row.get("id")))
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(session_id) => Ok(session_id),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(format!(
+                    "failed to add session for account {}",
+                    account_id.0
+                )))
+            }
+        }
+    }
+
+    /// Checks a session is active and marks it as seen now.
+    ///
+    /// `last_seen` is only written once it's older than a minute, so requests don't each write.
+    /// Returns false if the session doesn't exist, was revoked or expired.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn touch_session(
+        &self,
+        session_id: &SessionId,
+        account_id: &AccountId,
+    ) -> Result<bool, Error> {
+        let stale = match sqlx::query(
+            "SELECT last_seen < NOW() - INTERVAL '1 minute' AS stale FROM sessions
+            WHERE id = $1 AND account_id = $2 AND revoked_on IS NULL AND expires_on > NOW()",
+        )
+        .bind(session_id.0)
+        .bind(account_id.0)
+        .map(|row: PgRow| row.get::<bool, _>("stale"))
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(Some(stale)) => stale,
+            Ok(None) => return Ok(false),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                return Err(Error::DatabaseQueryError(
+                    "failed to query sessions".to_string(),
+                ));
+            }
+        };
+        if !stale {
+            return Ok(true);
+        }
+
+        match sqlx::query("UPDATE sessions SET last_seen = NOW() WHERE id = $1")
+            .bind(session_id.0)
+            .execute(&self.connection)
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(
+                    "failed to update sessions".to_string(),
+                ))
+            }
+        }
+    }
+
+    /// Returns the active sessions of account_id, most recently seen first.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn get_sessions(&self, account_id: &AccountId) -> Result<Vec<SessionInfo>, Error> {
+        match sqlx::query(
+            "SELECT * FROM sessions
+            WHERE account_id = $1 AND revoked_on IS NULL AND expires_on > NOW()
+            ORDER BY last_seen DESC",
+        )
+        .bind(account_id.0)
+        .map(|row: PgRow| SessionInfo {
+            id: SessionId(row.get("id")),
+            user_agent: row.get("user_agent"),
+            ip: row.get("ip"),
+            created_on: row.get("created_on"),
+            last_seen: row.get("last_seen"),
+            current: false,
+        })
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(sessions) => Ok(sessions),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(
+                    "failed to query sessions".to_string(),
+                ))
+            }
+        }
+    }
+
+    /// Revokes session_id if it belongs to account_id.
+    ///
+    /// Returns false if there was no such active session.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn revoke_session(
+        &self,
+        session_id: i32,
+        account_id: &AccountId,
+    ) -> Result<bool, Error> {
+        match sqlx::query(
+            "UPDATE sessions SET revoked_on = NOW()
+            WHERE id = $1 AND account_id = $2 AND revoked_on IS NULL",
+        )
+        .bind(session_id)
+        .bind(account_id.0)
+        .execute(&self.connection)
+        .await
+        {
+            Ok(res) => Ok(res.rows_affected() > 0),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(format!(
+                    "failed to revoke session {}",
+                    session_id
+                )))
+            }
+        }
+    }
+}
+
+fn api_key_from_row(row: &PgRow) -> ApiKey {
+    ApiKey {
+        id: ApiKeyId(row.get("id")),
+        name: row.get("name"),
+        prefix: row.get("prefix"),
+        scopes: row
+            .get::<Vec<String>, _>("scopes")
+            .iter()
+            .filter_map(|s| s.parse::<Scope>().ok())
+            .collect(),
+        expires_on: row.get("expires_on"),
+        last_used: row.get("last_used"),
+        created_on: row.get("created_on"),
+    }
+}
+
+/// Selects tags with their usage count and synonyms for `tag_from_row`, to add a WHERE to.
+const SELECT_TAGS: &str = "SELECT tags.name, tags.description, tags.wiki,
+        (SELECT COUNT(*) FROM questions
+            WHERE questions.tags @> ARRAY[tags.name] and questions.deleted_at IS NULL) AS questions,
+        ARRAY(SELECT name FROM tag_synonyms WHERE tag_id = tags.id ORDER BY name) AS synonyms
+    FROM tags";
+
+fn tag_from_row(row: PgRow) -> Tag {
+    Tag {
+        name: row.get("name"),
+        description: row.get("description"),
+        wiki: row.get("wiki"),
+        questions: row.get("questions"),
+        synonyms: row.get("synonyms"),
+    }
+}
+
+/// Condition on notifications leaving out those about deleted questions and answers.
+const NOTIFICATION_VISIBLE: &str =
+    "question_id IN (SELECT id FROM questions WHERE deleted_at IS NULL)
+    and (answer_id IS NULL OR answer_id IN (SELECT id FROM answers WHERE deleted_at IS NULL))";
+
+/// Returns None for a kind this version doesn't know about.
+fn notification_from_row(row: PgRow) -> Option<Notification> {
+    Some(Notification {
+        id: NotificationId(row.get("id")),
+        kind: row.get::<&str, _>("kind").parse().ok()?,
+        question_id: QuestionId(row.get("question_id")),
+        answer_id: row.get::<Option<i32>, _>("answer_id").map(AnswerId),
+        actor: AccountId(row.get("actor_id")),
+        read_at: row.get("read_at"),
+        created_on: row.get("created_on"),
+    })
+}
+
+fn feed_item_from_row(row: PgRow) -> FeedItem {
+    let author = AccountId(row.get("account_id"));
+    let at = row.get("at");
+    if row.get::<&str, _>("kind") == "answer" {
+        FeedItem::Answer {
+            answer: Answer {
+                id: AnswerId(row.get("id")),
+                content: row.get("content"),
+                question_id: QuestionId(row.get("question_id")),
+                version: row.get("version"),
+            },
+            author,
+            at,
+        }
+    } else {
+        FeedItem::Question {
+            question: Question {
+                id: QuestionId(row.get("id")),
+                title: row.get("title"),
+                content: row.get("content"),
+                tags: row.get("tags"),
+                version: row.get("version"),
+            },
+            author,
+            at,
+        }
+    }
+}
+
+fn question_with_author(row: PgRow) -> (Question, AccountId) {
+    (
+        Question {
+            id: QuestionId(row.get("id")),
+            title: row.get("title"),
+            content: row.get("content"),
+            tags: row.get("tags"),
+            version: row.get("version"),
+        },
+        AccountId(row.get("account_id")),
+    )
+}
+
+fn answer_with_author(row: PgRow) -> (Answer, AccountId) {
+    (
+        Answer {
+            id: AnswerId(row.get("id")),
+            content: row.get("content"),
+            question_id: QuestionId(row.get("question_id")),
+            version: row.get("version"),
+        },
+        AccountId(row.get("account_id")),
+    )
 }
@@ -0,0 +1,100 @@
+use opentelemetry::{global, trace::TracerProvider as _, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{propagation::TraceContextPropagator, runtime, trace, Resource};
+use serde::{Deserialize, Serialize};
+use tracing::Level;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::{
+    filter::Targets,
+    fmt::{format::FmtSpan, writer::BoxMakeWriter},
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+    EnvFilter, Layer,
+};
+
+use crate::config::LogConfig;
+
+#[derive(clap::ValueEnum, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable lines
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+/// Sets up logging as configured, and span export over OTLP/HTTP if an endpoint is set.
+///
+/// When logging to files the returned guard has to be kept until exit, dropping it flushes the
+/// remaining lines. Fails if the exporter can't be set up.
+pub fn init(config: &LogConfig) -> Result<Option<WorkerGuard>, String> {
+    // W3C trace context is used for incoming and outgoing requests even without an exporter, so
+    // trace ids still get passed along.
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let (writer, guard) = match &config.dir {
+        Some(dir) => {
+            let (writer, guard) =
+                tracing_appender::non_blocking(tracing_appender::rolling::daily(dir, "book.log"));
+            (BoxMakeWriter::new(writer), Some(guard))
+        }
+        None => (BoxMakeWriter::new(std::io::stdout), None),
+    };
+    let fmt = match config.format {
+        LogFormat::Text => tracing_subscriber::fmt::layer()
+            // Record an event when each span closes, this can be used for routes
+            // duration.
+            .with_span_events(FmtSpan::CLOSE)
+            .with_ansi(config.dir.is_none())
+            .with_writer(writer)
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_span_events(FmtSpan::CLOSE)
+            .with_writer(writer)
+            .boxed(),
+    }
+    // Use the filter we built to determine which traces to record
+    .with_filter(EnvFilter::new(config.filter()));
+
+    let otel = match config.otlp_traces_url() {
+        Some(url) => Some(otel_layer(url)?),
+        None => None,
+    };
+
+    tracing_subscriber::registry().with(fmt).with(otel).init();
+    Ok(guard)
+}
+
+/// Exports spans to url over OTLP/HTTP.
+fn otel_layer<S>(url: String) -> Result<impl Layer<S>, String>
+where
+    S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+{
+    let provider = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().http().with_endpoint(url))
+        .with_trace_config(
+            trace::Config::default()
+                .with_resource(Resource::new(vec![KeyValue::new("service.name", "book")])),
+        )
+        .install_batch(runtime::Tokio)
+        .map_err(|e| format!("cannot set up OTLP export: {}", e))?;
+    let tracer = provider.tracer("book");
+    global::set_tracer_provider(provider);
+
+    // Our own spans, including the DB ones at debug level, but only coarse ones from
+    // dependencies.
+    Ok(tracing_opentelemetry::layer()
+        .with_tracer(tracer)
+        .with_filter(
+            Targets::new()
+                .with_target("book", Level::DEBUG)
+                .with_default(Level::INFO),
+        ))
+}
+
+/// Flushes spans that haven't been exported yet.
+pub fn shutdown() {
+    global::shutdown_tracer_provider();
+}
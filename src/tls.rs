@@ -0,0 +1,92 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use tokio::signal::unix::{signal, SignalKind};
+use tokio_rustls::rustls::{
+    crypto::ring,
+    pki_types::{CertificateDer, PrivateKeyDer},
+    ServerConfig,
+};
+use tokio_rustls::TlsAcceptor;
+
+use crate::config::TlsConfig;
+
+fn open(path: &Path) -> Result<BufReader<File>, String> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| format!("cannot read {}: {}", path.display(), e))
+}
+
+/// Reads the PEM encoded certificate chain and private key.
+fn load(config: &TlsConfig) -> Result<Arc<ServerConfig>, String> {
+    let certs = rustls_pemfile::certs(&mut open(&config.cert)?)
+        .collect::<Result<Vec<CertificateDer>, _>>()
+        .map_err(|e| format!("cannot parse {}: {}", config.cert.display(), e))?;
+    if certs.is_empty() {
+        return Err(format!("no certificate in {}", config.cert.display()));
+    }
+    let key: PrivateKeyDer = rustls_pemfile::private_key(&mut open(&config.key)?)
+        .map_err(|e| format!("cannot parse {}: {}", config.key.display(), e))?
+        .ok_or_else(|| format!("no private key in {}", config.key.display()))?;
+
+    let mut server_config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("invalid certificate or key: {}", e))?;
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(Arc::new(server_config))
+}
+
+/// Certificate and key for new TLS connections, which can be swapped while running.
+#[derive(Clone)]
+pub struct Acceptor {
+    config: TlsConfig,
+    current: Arc<RwLock<Arc<ServerConfig>>>,
+}
+
+impl Acceptor {
+    pub fn new(config: &TlsConfig) -> Result<Self, String> {
+        Ok(Acceptor {
+            current: Arc::new(RwLock::new(load(config)?)),
+            config: config.clone(),
+        })
+    }
+
+    pub fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(self.current.read().unwrap().clone())
+    }
+
+    /// Reads the certificate and key again on every SIGHUP, so rotated certificates are picked
+    /// up without a restart. Connections already open keep the old ones.
+    ///
+    /// If they can't be loaded the old ones stay in use.
+    pub fn reload_on_sighup(&self) {
+        let acceptor = self.clone();
+        tokio::spawn(async move {
+            let mut hangups = match signal(SignalKind::hangup()) {
+                Ok(hangups) => hangups,
+                Err(e) => {
+                    tracing::event!(tracing::Level::ERROR, "cannot listen for SIGHUP: {}", e);
+                    return;
+                }
+            };
+            while hangups.recv().await.is_some() {
+                match load(&acceptor.config) {
+                    Ok(server_config) => {
+                        *acceptor.current.write().unwrap() = server_config;
+                        tracing::event!(tracing::Level::INFO, "reloaded TLS certificate");
+                    }
+                    Err(e) => tracing::event!(
+                        tracing::Level::ERROR,
+                        "cannot reload TLS certificate, keeping the old one: {}",
+                        e
+                    ),
+                }
+            }
+        });
+    }
+}
@@ -1,5 +1,8 @@
 use chrono::prelude::*;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::api_key::{ApiKeyId, Scope};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Session {
@@ -7,9 +10,34 @@ pub struct Session {
     pub account_id: AccountId,
     // not before
     pub nbf: DateTime<Utc>,
+    /// Scopes of the API key the session was authenticated with.
+    /// None for sessions from a password login, which may do anything.
+    #[serde(default)]
+    pub scopes: Option<Vec<Scope>>,
+    /// The recorded session a `login` token belongs to, None for API keys.
+    #[serde(default)]
+    pub session_id: Option<SessionId>,
+    /// The API key the session was authenticated with, None for `login` tokens.
+    #[serde(default)]
+    pub api_key_id: Option<ApiKeyId>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+impl Session {
+    /// Returns true if the session may perform actions covered by scope.
+    pub fn allows(&self, scope: Scope) -> bool {
+        match &self.scopes {
+            Some(scopes) => scopes.contains(&scope),
+            None => true,
+        }
+    }
+
+    /// Returns true if the session comes from a password login rather than an API key.
+    pub fn is_login(&self) -> bool {
+        self.scopes.is_none()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
 pub struct Account {
     /// ID is not provided by the user, output param generated from server.
     pub id: Option<AccountId>,
@@ -17,5 +45,39 @@ pub struct Account {
     pub password: String,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+/// Keeps passwords and their hashes out of logs.
+impl std::fmt::Debug for Account {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Account")
+            .field("id", &self.id)
+            .field("email", &self.email)
+            .field("password", &"[redacted]")
+            .finish()
+    }
+}
+
+/// An account as shown to its owner, without the password.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct AccountInfo {
+    pub id: AccountId,
+    pub email: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash, ToSchema)]
 pub struct AccountId(pub i32);
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash, ToSchema)]
+pub struct SessionId(pub i32);
+
+/// A device an account is logged in from, as listed to its owner.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct SessionInfo {
+    pub id: SessionId,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created_on: DateTime<Utc>,
+    /// Updated at most once a minute.
+    pub last_seen: DateTime<Utc>,
+    /// True for the session the listing was requested with.
+    pub current: bool,
+}
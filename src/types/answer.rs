@@ -1,16 +1,20 @@
 use serde::{Deserialize, Serialize};
-#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+use utoipa::ToSchema;
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash, ToSchema)]
 pub struct AnswerId(pub i32);
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct Answer {
     pub id: AnswerId,
     pub content: String,
     pub question_id: super::question::QuestionId,
+    /// Bumped on every update, sent as the `ETag` rather than in the body.
+    #[serde(skip)]
+    pub version: i32,
 }
 
 /// Used to create Answer's as id is an output param.
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct NewAnswer {
     pub content: String,
     pub question_id: super::question::QuestionId,
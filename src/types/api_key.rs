@@ -0,0 +1,79 @@
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// What an API key is allowed to do.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    /// Read the account itself, its follows, feed and notifications. Questions and answers can be
+    /// read without any key.
+    Read,
+    PostQuestions,
+    PostAnswers,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::PostQuestions => "post_questions",
+            Scope::PostAnswers => "post_answers",
+        }
+    }
+}
+
+impl std::str::FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(Scope::Read),
+            "post_questions" => Ok(Scope::PostQuestions),
+            "post_answers" => Ok(Scope::PostAnswers),
+            _ => Err(format!("unknown scope {}", s)),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash, ToSchema)]
+pub struct ApiKeyId(pub i32);
+
+/// An API key as listed to its owner, the key itself is only shown once on creation.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct ApiKey {
+    pub id: ApiKeyId,
+    pub name: String,
+    /// First characters of the key, to tell keys apart.
+    pub prefix: String,
+    pub scopes: Vec<Scope>,
+    pub expires_on: Option<DateTime<Utc>>,
+    pub last_used: Option<DateTime<Utc>>,
+    pub created_on: DateTime<Utc>,
+}
+
+/// Used to create API keys, as the id and key are generated by the server.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct NewApiKey {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub expires_on: Option<DateTime<Utc>>,
+}
+
+/// Returned once when a key is created, holds the only copy of the plain key.
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+    pub key: String,
+}
+
+/// Keeps the key out of logs.
+impl std::fmt::Debug for CreatedApiKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CreatedApiKey")
+            .field("api_key", &self.api_key)
+            .field("key", &"[redacted]")
+            .finish()
+    }
+}
@@ -0,0 +1,75 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::account::AccountId;
+use super::answer::AnswerId;
+use super::notification::Notification;
+use super::question::QuestionId;
+
+/// A change streamed to clients, as sent on the `book_events` channel by the triggers in the
+/// events migrations, with the tags of the question looked up.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Event {
+    QuestionCreated {
+        question_id: QuestionId,
+        tags: Vec<String>,
+    },
+    /// The question was edited, rolled back, retagged or restored.
+    QuestionUpdated {
+        question_id: QuestionId,
+        tags: Vec<String>,
+    },
+    QuestionDeleted {
+        question_id: QuestionId,
+        tags: Vec<String>,
+    },
+    AnswerCreated {
+        question_id: QuestionId,
+        answer_id: AnswerId,
+        /// Tags of the question answered.
+        tags: Vec<String>,
+    },
+    /// Only sent to the account notified.
+    Notification {
+        #[serde(skip_serializing)]
+        account_id: AccountId,
+        notification: Notification,
+    },
+}
+
+impl Event {
+    /// Name of the event in SSE streams.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::QuestionCreated { .. } => "question_created",
+            Event::QuestionUpdated { .. } => "question_updated",
+            Event::QuestionDeleted { .. } => "question_deleted",
+            Event::AnswerCreated { .. } => "answer_created",
+            Event::Notification { .. } => "notification",
+        }
+    }
+
+    /// Returns true if a client subscribed with filter, and logged in as account if any, gets the
+    /// event. Notifications ignore the filter but only go to their account.
+    pub fn matches(&self, filter: &EventFilter, account: Option<&AccountId>) -> bool {
+        let (question_id, tags) = match self {
+            Event::QuestionCreated { question_id, tags }
+            | Event::QuestionUpdated { question_id, tags }
+            | Event::QuestionDeleted { question_id, tags }
+            | Event::AnswerCreated {
+                question_id, tags, ..
+            } => (question_id, tags),
+            Event::Notification { account_id, .. } => return account == Some(account_id),
+        };
+        filter.question_id.is_none_or(|id| question_id.0 == id)
+            && filter.tag.as_ref().is_none_or(|tag| tags.contains(tag))
+    }
+}
+
+/// Query params of the event streams, narrowing the events sent to those of a tag or question.
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct EventFilter {
+    pub tag: Option<String>,
+    pub question_id: Option<i32>,
+}
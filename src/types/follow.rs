@@ -0,0 +1,37 @@
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::account::AccountId;
+use super::answer::Answer;
+use super::question::{Question, QuestionId};
+
+/// What an account follows to see in its feed.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct Follows {
+    pub tags: Vec<String>,
+    pub questions: Vec<QuestionId>,
+}
+
+/// A new question in a followed tag, or a new answer on a followed question.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FeedItem {
+    Question {
+        question: Question,
+        author: AccountId,
+        at: DateTime<Utc>,
+    },
+    Answer {
+        answer: Answer,
+        author: AccountId,
+        at: DateTime<Utc>,
+    },
+}
+
+/// Query params of the feed.
+#[derive(Deserialize, Debug, Default)]
+pub struct FeedQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
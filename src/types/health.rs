@@ -0,0 +1,51 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::profanity::CircuitState;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Ok,
+    Failed,
+}
+
+/// Result of a single dependency check.
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct Check {
+    pub status: CheckStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct MigrationsCheck {
+    pub status: CheckStatus,
+    /// Versions of migrations not applied to the DB yet.
+    pub pending: Vec<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The content filter only degrades question and answer posting, so it doesn't affect
+/// readiness.
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct ContentFilterCheck {
+    /// Failed while the circuit breaker is open.
+    pub status: CheckStatus,
+    pub circuit: CircuitState,
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct ReadinessChecks {
+    pub database: Check,
+    pub migrations: MigrationsCheck,
+    pub content_filter: ContentFilterCheck,
+}
+
+/// Body of `/readyz`.
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct Readiness {
+    pub ready: bool,
+    pub checks: ReadinessChecks,
+}
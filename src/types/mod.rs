@@ -1,4 +1,11 @@
 pub mod account;
 pub mod answer;
+pub mod api_key;
+pub mod event;
+pub mod follow;
+pub mod health;
+pub mod notification;
 pub mod pagination;
 pub mod question;
+pub mod revision;
+pub mod tag;
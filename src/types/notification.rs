@@ -0,0 +1,128 @@
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::account::AccountId;
+use super::answer::AnswerId;
+use super::question::QuestionId;
+
+/// What a notification is about.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    /// The account's question got an answer.
+    Answer,
+    /// The account was mentioned in a question or answer.
+    Mention,
+}
+
+impl NotificationKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotificationKind::Answer => "answer",
+            NotificationKind::Mention => "mention",
+        }
+    }
+}
+
+impl std::str::FromStr for NotificationKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "answer" => Ok(NotificationKind::Answer),
+            "mention" => Ok(NotificationKind::Mention),
+            _ => Err(format!("unknown notification kind {}", s)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash, ToSchema)]
+pub struct NotificationId(pub i32);
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct Notification {
+    pub id: NotificationId,
+    pub kind: NotificationKind,
+    pub question_id: QuestionId,
+    /// The answer that was added or mentioned the account, None for mentions in questions.
+    pub answer_id: Option<AnswerId>,
+    /// Who asked or answered.
+    pub actor: AccountId,
+    pub read_at: Option<DateTime<Utc>>,
+    pub created_on: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct UnreadCount {
+    pub unread: i64,
+}
+
+/// Query params of the notification listing.
+#[derive(Deserialize, Debug, Default)]
+pub struct NotificationQuery {
+    /// Only unread notifications.
+    #[serde(default)]
+    pub unread: bool,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// Returns the lower cased emails mentioned in text as `@` followed by the email, e.g.
+/// `@ada@example.com`, as accounts have no other name.
+pub fn mentions(text: &str) -> Vec<String> {
+    let mut emails: Vec<String> = text
+        .split_whitespace()
+        .filter_map(|word| word.strip_prefix('@'))
+        .map(|email| email.trim_end_matches(|c: char| !c.is_alphanumeric()))
+        .filter(|email| {
+            email
+                .split_once('@')
+                .is_some_and(|(user, domain)| !user.is_empty() && domain.contains('.'))
+        })
+        .map(|email| email.to_lowercase())
+        .collect();
+    emails.sort();
+    emails.dedup();
+    emails
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_mentions() {
+        assert_eq!(
+            mentions("Thanks @ada@example.com, see @Bob@Example.org"),
+            ["ada@example.com", "bob@example.org"]
+        );
+    }
+
+    #[test]
+    fn trims_trailing_punctuation() {
+        assert_eq!(mentions("cc @a@b.com,"), ["a@b.com"]);
+        assert_eq!(mentions("ask @a@b.com."), ["a@b.com"]);
+        assert_eq!(mentions("right, @a@b.com?!"), ["a@b.com"]);
+    }
+
+    #[test]
+    fn mentions_each_email_once() {
+        assert_eq!(
+            mentions("@a@b.com and @A@B.com, again @a@b.com"),
+            ["a@b.com"]
+        );
+    }
+
+    #[test]
+    fn ignores_at_signs_inside_words() {
+        assert!(mentions("mail a@b.com or x@a@b.com").is_empty());
+        assert!(mentions("@@b.com @a@localhost @").is_empty());
+    }
+
+    #[test]
+    fn no_mentions() {
+        assert!(mentions("").is_empty());
+        assert!(mentions("How do lifetimes work?").is_empty());
+    }
+}
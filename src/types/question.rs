@@ -1,20 +1,59 @@
-use serde::{Deserialize, Serialize};
-#[derive(Clone, Debug, Deserialize, Serialize)]
+use serde::{Deserialize, Deserializer, Serialize};
+use utoipa::ToSchema;
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema)]
 pub struct Question {
     pub id: QuestionId,
     pub title: String,
     pub content: String,
     pub tags: Option<Vec<String>>,
+    /// Bumped on every update, sent as the `ETag` rather than in the body.
+    #[serde(skip)]
+    pub version: i32,
 }
 
 /// Like Question but without an ID.
 /// Used for creating new questions, as ID is an output parameter.
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema)]
 pub struct NewQuestion {
     pub title: String,
     pub content: String,
     pub tags: Option<Vec<String>>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+/// A Question as edited, with an optional summary kept in its revision.
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema)]
+pub struct QuestionEdit {
+    #[serde(flatten)]
+    pub question: Question,
+    pub summary: Option<String>,
+}
+
+/// An RFC 7396 merge patch of a Question, fields left out stay as they are.
+///
+/// Title and content can't be null, null tags remove them.
+#[derive(Clone, Debug, Default, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct QuestionPatch {
+    #[serde(default, deserialize_with = "present")]
+    pub title: Option<String>,
+    #[serde(default, deserialize_with = "present")]
+    pub content: Option<String>,
+    #[serde(default, deserialize_with = "present")]
+    #[schema(value_type = Option<Vec<String>>)]
+    pub tags: Option<Option<Vec<String>>>,
+    /// Kept in the revision of the edit rather than patched into the question.
+    #[serde(default)]
+    pub summary: Option<String>,
+}
+
+/// Deserializes a field that is present, so a null can be told apart from a missing field.
+fn present<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash, ToSchema)]
 pub struct QuestionId(pub i32);
@@ -0,0 +1,62 @@
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use similar::TextDiff;
+use utoipa::ToSchema;
+
+use super::account::AccountId;
+
+/// A question as it was after an edit, version 1 being how it was asked.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct Revision {
+    pub version: i32,
+    pub title: String,
+    pub content: String,
+    pub tags: Option<Vec<String>>,
+    /// The account that made the edit, the owner or a moderator.
+    pub editor: AccountId,
+    pub summary: Option<String>,
+    pub created_on: DateTime<Utc>,
+    /// Changes since the previous revision, None for the first one.
+    pub diff: Option<RevisionDiff>,
+}
+
+/// Changes between two revisions, title and content as unified diffs if they changed.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct RevisionDiff {
+    pub title: Option<String>,
+    pub content: Option<String>,
+    pub tags_added: Vec<String>,
+    pub tags_removed: Vec<String>,
+}
+
+impl RevisionDiff {
+    pub fn between(old: &Revision, new: &Revision) -> Self {
+        let unified = |field: &str, old_text: &str, new_text: &str| {
+            (old_text != new_text).then(|| {
+                TextDiff::from_lines(old_text, new_text)
+                    .unified_diff()
+                    .header(
+                        &format!("{} v{}", field, old.version),
+                        &format!("{} v{}", field, new.version),
+                    )
+                    .to_string()
+            })
+        };
+        let old_tags = old.tags.as_deref().unwrap_or_default();
+        let new_tags = new.tags.as_deref().unwrap_or_default();
+        RevisionDiff {
+            title: unified("title", &old.title, &new.title),
+            content: unified("content", &old.content, &new.content),
+            tags_added: new_tags
+                .iter()
+                .filter(|tag| !old_tags.contains(tag))
+                .cloned()
+                .collect(),
+            tags_removed: old_tags
+                .iter()
+                .filter(|tag| !new_tags.contains(tag))
+                .cloned()
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// A tag questions can have, by its canonical name.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct Tag {
+    pub name: String,
+    pub description: Option<String>,
+    /// Longer text for the tag's page.
+    pub wiki: Option<String>,
+    /// How many questions have the tag, not counting deleted ones.
+    pub questions: i64,
+    /// Names that are replaced by this tag when questions are asked or edited.
+    pub synonyms: Vec<String>,
+}
+
+/// The parts of a tag moderators edit.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct TagEdit {
+    pub description: Option<String>,
+    pub wiki: Option<String>,
+}
+
+/// Where to merge a tag into.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct TagMerge {
+    pub into: String,
+}
+
+/// Query params of the tag listing.
+#[derive(Deserialize, Debug, Default)]
+pub struct TagQuery {
+    /// Only tags whose name or a synonym starts with it, for autocomplete.
+    pub prefix: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// Returns name the way tags are stored: lower case, with runs of whitespace and underscores
+/// replaced by a dash and no dashes at either end. Matches the tags migration that normalized the
+/// existing tags.
+pub fn normalize(name: &str) -> String {
+    name.to_lowercase()
+        .split(|c: char| c.is_whitespace() || c == '_')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+        .trim_matches('-')
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folds_case() {
+        assert_eq!(normalize("Rust"), "rust");
+        assert_eq!(normalize("WebAssembly"), "webassembly");
+    }
+
+    #[test]
+    fn collapses_whitespace_and_underscores() {
+        assert_eq!(normalize("web dev"), "web-dev");
+        assert_eq!(normalize("web_dev"), "web-dev");
+        assert_eq!(normalize("web \t_ dev"), "web-dev");
+        // Dashes are kept as they are, like in the migration.
+        assert_eq!(normalize("web-_dev"), "web--dev");
+        assert_eq!(normalize("c++"), "c++");
+    }
+
+    #[test]
+    fn trims_the_ends() {
+        assert_eq!(normalize("  rust  "), "rust");
+        assert_eq!(normalize("_rust"), "rust");
+        assert_eq!(normalize("-rust-"), "rust");
+        assert_eq!(normalize(" _-rust_ "), "rust");
+        assert_eq!(normalize("__"), "");
+        assert_eq!(normalize("-"), "");
+    }
+
+    /// The cases above are what this expression of the tags migration does, changing either
+    /// means changing both.
+    #[test]
+    fn matches_the_tags_migration() {
+        let migration = include_str!("../../migrations/20261018170000_tags.up.sql");
+        assert!(migration
+            .contains("btrim(regexp_replace(lower(trim(tag)), '[[:space:]_]+', '-', 'g'), '-')"));
+    }
+}
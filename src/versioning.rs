@@ -0,0 +1,44 @@
+use warp::http::HeaderValue;
+use warp::path::FullPath;
+use warp::Reply;
+
+/// First path segments of the `/v1` routes that are still served at the root.
+pub const ROOT_ALIASES: &[&str] = &[
+    "questions",
+    "answers",
+    "registration",
+    "login",
+    "oidc",
+    "accounts",
+];
+
+/// When the root aliases were deprecated, as an RFC 9745 date.
+const ROOT_DEPRECATED: &str = "@1792281600";
+/// When the root aliases will be removed, as an RFC 8594 HTTP date.
+const ROOT_SUNSET: &str = "Sun, 18 Apr 2027 00:00:00 GMT";
+
+/// Returns true if path is served at the root only for clients that predate `/v1`.
+pub fn is_root_alias(path: &str) -> bool {
+    let first = path.trim_start_matches('/').split('/').next();
+    first.is_some_and(|segment| ROOT_ALIASES.contains(&segment))
+}
+
+/// Marks replies to root aliases as deprecated, pointing to the same route under `/v1`.
+///
+/// Applied after errors were turned into replies so clients see it on those as well.
+pub fn deprecate_root_aliases(path: FullPath, reply: impl Reply) -> warp::reply::Response {
+    let mut res = reply.into_response();
+    if !is_root_alias(path.as_str()) {
+        return res;
+    }
+    let headers = res.headers_mut();
+    headers.insert("Deprecation", HeaderValue::from_static(ROOT_DEPRECATED));
+    headers.insert("Sunset", HeaderValue::from_static(ROOT_SUNSET));
+    if let Ok(link) = HeaderValue::from_str(&format!(
+        "</v1{}>; rel=\"successor-version\"",
+        path.as_str()
+    )) {
+        headers.insert("Link", link);
+    }
+    res
+}